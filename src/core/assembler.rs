@@ -0,0 +1,548 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Source syntax
+//
+// ; comment
+// define SPEED 3          Constant, usable wherever a number is expected
+// include "sprites.c8s"   Insert another file, relative to the current one
+// loop:                   Label, may be followed by an instruction
+//     LD V0, SPEED        Instructions use the mnemonics of the exec_* docs
+//     JP loop
+// sprite:
+//     db 0x80, 0x40       Bytes
+//     dw 0x1234, loop     Big-endian words
+//     LD I, LONG sprite   XO-CHIP 16-bit address, takes 4 bytes
+//
+// Numbers are decimal, hexadecimal (0x1F or $1F) or binary (0b0101).
+// Operands can be combined with + and -, e.g. `LD I, sprite + 5`.
+
+const PROGRAM_START:    u16 = 0x200;
+const PROGRAM_SIZE:   usize = 0x10000 - 0x200;
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Error found while assembling, with the position of the faulty line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssemblerError {
+    pub file: String,
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssemblerError {}
+
+/// Assemble a source file into a ROM that loads at 0x200.
+///
+/// # Arguments
+///
+/// * `path` - Path of the source file, includes are relative to it
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AssemblerError> {
+    let mut assembler = Assembler::new();
+    assembler.read_file(path.as_ref(), None, 0)?;
+    assembler.assemble()
+}
+
+/// Assemble source code into a ROM that loads at 0x200.
+///
+/// Includes are relative to the current directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError> {
+    let mut assembler = Assembler::new();
+    assembler.read_source(source, Path::new("<source>"), 0)?;
+    assembler.assemble()
+}
+
+/// Statement of the program, once labels and directives have been split.
+enum Statement {
+    Instruction(String, Vec<String>),
+    Bytes(Vec<String>),
+    Words(Vec<String>)
+}
+
+/// A statement and where it comes from.
+struct Line {
+    file: String,
+    number: usize,
+    address: usize,
+    statement: Statement
+}
+
+/// Value of a `define`, evaluated when it is used.
+struct Define {
+    file: String,
+    number: usize,
+    value: String
+}
+
+struct Assembler {
+    lines: Vec<Line>,
+    labels: HashMap<String, usize>,
+    defines: HashMap<String, Define>,
+    address: usize
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Assembler {
+            lines: Vec::new(),
+            labels: HashMap::new(),
+            defines: HashMap::new(),
+            address: PROGRAM_START as usize
+        }
+    }
+
+    fn read_file(&mut self, path: &Path, from: Option<(&str, usize)>, depth: usize) -> Result<(), AssemblerError> {
+        let source = fs::read_to_string(path).map_err(|e| {
+            let message = format!("can't read {}: {}", path.display(), e);
+            match from {
+                Some((file, line)) => error(file, line, message),
+                None => error(&path.display().to_string(), 0, message)
+            }
+        })?;
+
+        self.read_source(&source, path, depth)
+    }
+
+    /// First pass: split the lines into statements, follow the includes
+    /// and give an address to every label.
+    fn read_source(&mut self, source: &str, path: &Path, depth: usize) -> Result<(), AssemblerError> {
+        let file = path.display().to_string();
+
+        for (index, text) in source.lines().enumerate() {
+            let number = index + 1;
+            let mut text = strip_comment(text).trim();
+
+            // Labels
+            while let Some(colon) = text.find(':') {
+                let label = text[.. colon].trim();
+                if !is_identifier(label) {
+                    return Err(error(&file, number, format!("invalid label `{}`", label)));
+                }
+                if is_reserved(label) {
+                    return Err(error(&file, number, format!("`{}` is a reserved name", label)));
+                }
+                if self.labels.insert(label.to_string(), self.address).is_some() ||
+                   self.defines.contains_key(label) {
+                    return Err(error(&file, number, format!("`{}` is already defined", label)));
+                }
+                text = text[colon + 1 ..].trim();
+            }
+
+            if text.is_empty() {
+                continue;
+            }
+
+            let (keyword, rest) = match text.find(char::is_whitespace) {
+                Some(space) => (&text[.. space], text[space ..].trim()),
+                None => (text, "")
+            };
+
+            let statement = match keyword.to_ascii_lowercase().as_str() {
+                "define" => {
+                    let (name, value) = match rest.find(char::is_whitespace) {
+                        Some(space) => (&rest[.. space], rest[space ..].trim()),
+                        None => return Err(error(&file, number, "usage: define <name> <value>".to_string()))
+                    };
+                    if !is_identifier(name) || is_reserved(name) {
+                        return Err(error(&file, number, format!("invalid constant name `{}`", name)));
+                    }
+                    if self.labels.contains_key(name) || self.defines.contains_key(name) {
+                        return Err(error(&file, number, format!("`{}` is already defined", name)));
+                    }
+                    self.defines.insert(name.to_string(), Define {
+                        file: file.clone(),
+                        number,
+                        value: value.to_string()
+                    });
+                    continue;
+                },
+                "include" => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(error(&file, number, "too many nested includes".to_string()));
+                    }
+                    let name = rest.trim_matches('"');
+                    if name.is_empty() {
+                        return Err(error(&file, number, "usage: include \"file\"".to_string()));
+                    }
+                    let included = path.parent().map(|dir| dir.join(name)).unwrap_or_else(|| PathBuf::from(name));
+                    self.read_file(&included, Some((&file, number)), depth + 1)?;
+                    continue;
+                },
+                "db" => Statement::Bytes(split_operands(rest)),
+                "dw" => Statement::Words(split_operands(rest)),
+                _    => Statement::Instruction(keyword.to_ascii_uppercase(), split_operands(rest))
+            };
+
+            let size = match &statement {
+                Statement::Instruction(_, operands) => {
+                    let long = operands.iter().any(|operand| matches!(Operand::parse(operand), Operand::Long(_)));
+                    if long { 4 } else { 2 }
+                },
+                Statement::Bytes(values) => values.len(),
+                Statement::Words(values) => 2 * values.len()
+            };
+
+            if self.address - PROGRAM_START as usize + size > PROGRAM_SIZE {
+                return Err(error(&file, number, "program is too big".to_string()));
+            }
+
+            self.lines.push(Line { file: file.clone(), number, address: self.address, statement });
+            self.address += size;
+        }
+
+        Ok(())
+    }
+
+    /// Second pass: encode every statement now that all labels are known.
+    fn assemble(&self) -> Result<Vec<u8>, AssemblerError> {
+        let mut rom = Vec::new();
+
+        for line in self.lines.iter() {
+            debug_assert_eq!(PROGRAM_START as usize + rom.len(), line.address);
+
+            let fail = |message: String| error(&line.file, line.number, message);
+
+            match &line.statement {
+                Statement::Instruction(mnemonic, operands) => {
+                    let bytes = self.encode(mnemonic, operands).map_err(fail)?;
+                    rom.extend_from_slice(&bytes);
+                },
+                Statement::Bytes(values) => {
+                    for value in values {
+                        rom.push(self.byte(value).map_err(fail)?);
+                    }
+                },
+                Statement::Words(values) => {
+                    for value in values {
+                        let word = self.evaluate(value, 0).map_err(fail)?;
+                        if !(-0x8000 ..= 0xFFFF).contains(&word) {
+                            return Err(fail(format!("`{}` does not fit in a word", value)));
+                        }
+                        rom.extend_from_slice(&(word as u16).to_be_bytes());
+                    }
+                }
+            }
+        }
+
+        Ok(rom)
+    }
+
+    fn encode(&self, mnemonic: &str, operands: &[String]) -> Result<Vec<u8>, String> {
+        let args: Vec<Operand> = operands.iter().map(|operand| Operand::parse(operand)).collect();
+
+        let opcode = match (mnemonic, args.as_slice()) {
+            ("CLS",  [])                                     => 0x00E0,
+            ("RET",  [])                                     => 0x00EE,
+            ("SCD",  [Operand::Value(n)])                    => 0x00C0 | self.nibble(n)?,
+            ("SCU",  [Operand::Value(n)])                    => 0x00D0 | self.nibble(n)?,
+            ("SCR",  [])                                     => 0x00FB,
+            ("SCL",  [])                                     => 0x00FC,
+            ("EXIT", [])                                     => 0x00FD,
+            ("LOW",  [])                                     => 0x00FE,
+            ("HIGH", [])                                     => 0x00FF,
+            ("SYS",  [Operand::Value(a)])                    => self.address(a)?,
+            ("JP",   [Operand::Value(a)])                    => 0x1000 | self.address(a)?,
+            ("JP",   [Operand::V(0), Operand::Value(a)])     => 0xB000 | self.address(a)?,
+            ("CALL", [Operand::Value(a)])                    => 0x2000 | self.address(a)?,
+            ("SE",   [Operand::V(x), Operand::V(y)])         => xy(0x5000, *x, *y),
+            ("SE",   [Operand::V(x), Operand::Value(b)])     => xkk(0x3000, *x, self.byte(b)?),
+            ("SAVE", [Operand::V(x), Operand::V(y)])         => xy(0x5002, *x, *y),
+            ("LOAD", [Operand::V(x), Operand::V(y)])         => xy(0x5003, *x, *y),
+            ("SNE",  [Operand::V(x), Operand::V(y)])         => xy(0x9000, *x, *y),
+            ("SNE",  [Operand::V(x), Operand::Value(b)])     => xkk(0x4000, *x, self.byte(b)?),
+            ("LD",   [Operand::V(x), Operand::V(y)])         => xy(0x8000, *x, *y),
+            ("LD",   [Operand::V(x), Operand::Value(b)])     => xkk(0x6000, *x, self.byte(b)?),
+            ("LD",   [Operand::I, Operand::Value(a)])        => 0xA000 | self.address(a)?,
+            ("LD",   [Operand::I, Operand::Long(a)])         => {
+                let mut bytes = vec![0xF0, 0x00];
+                bytes.extend_from_slice(&self.long_address(a)?.to_be_bytes());
+                return Ok(bytes);
+            },
+            ("LD",   [Operand::V(x), Operand::Dt])           => xy(0xF007, *x, 0),
+            ("LD",   [Operand::V(x), Operand::K])            => xy(0xF00A, *x, 0),
+            ("LD",   [Operand::Dt, Operand::V(x)])           => xy(0xF015, *x, 0),
+            ("LD",   [Operand::St, Operand::V(x)])           => xy(0xF018, *x, 0),
+            ("LD",   [Operand::F, Operand::V(x)])            => xy(0xF029, *x, 0),
+            ("LD",   [Operand::Hf, Operand::V(x)])           => xy(0xF030, *x, 0),
+            ("LD",   [Operand::B, Operand::V(x)])            => xy(0xF033, *x, 0),
+            ("LD",   [Operand::IndirectI, Operand::V(x)])    => xy(0xF055, *x, 0),
+            ("LD",   [Operand::V(x), Operand::IndirectI])    => xy(0xF065, *x, 0),
+            ("LD",   [Operand::R, Operand::V(x)])            => xy(0xF075, *x, 0),
+            ("LD",   [Operand::V(x), Operand::R])            => xy(0xF085, *x, 0),
+            ("ADD",  [Operand::V(x), Operand::V(y)])         => xy(0x8004, *x, *y),
+            ("ADD",  [Operand::V(x), Operand::Value(b)])     => xkk(0x7000, *x, self.byte(b)?),
+            ("ADD",  [Operand::I, Operand::V(x)])            => xy(0xF01E, *x, 0),
+            ("OR",   [Operand::V(x), Operand::V(y)])         => xy(0x8001, *x, *y),
+            ("AND",  [Operand::V(x), Operand::V(y)])         => xy(0x8002, *x, *y),
+            ("XOR",  [Operand::V(x), Operand::V(y)])         => xy(0x8003, *x, *y),
+            ("SUB",  [Operand::V(x), Operand::V(y)])         => xy(0x8005, *x, *y),
+            ("SHR",  [Operand::V(x)])                        => xy(0x8006, *x, 0),
+            ("SHR",  [Operand::V(x), Operand::V(y)])         => xy(0x8006, *x, *y),
+            ("SUBN", [Operand::V(x), Operand::V(y)])         => xy(0x8007, *x, *y),
+            ("SHL",  [Operand::V(x)])                        => xy(0x800E, *x, 0),
+            ("SHL",  [Operand::V(x), Operand::V(y)])         => xy(0x800E, *x, *y),
+            ("RND",  [Operand::V(x), Operand::Value(b)])     => xkk(0xC000, *x, self.byte(b)?),
+            ("DRW",  [Operand::V(x), Operand::V(y), Operand::Value(n)]) =>
+                xy(0xD000, *x, *y) | self.nibble(n)?,
+            ("SKP",  [Operand::V(x)])                        => xy(0xE09E, *x, 0),
+            ("SKNP", [Operand::V(x)])                        => xy(0xE0A1, *x, 0),
+            ("PLANE", [Operand::Value(n)])                   => 0xF001 | self.nibble(n)? << 8,
+            ("AUDIO", [])                                    => 0xF002,
+            ("PITCH", [Operand::V(x)])                       => xy(0xF03A, *x, 0),
+            _ => {
+                return Err(format!("invalid instruction `{} {}`", mnemonic, operands.join(", ")).trim().to_string());
+            }
+        };
+
+        Ok(opcode.to_be_bytes().to_vec())
+    }
+
+    fn address(&self, text: &str) -> Result<u16, String> {
+        match self.evaluate(text, 0)? {
+            value @ 0 ..= 0xFFF => Ok(value as u16),
+            _ => Err(format!("address `{}` is out of range", text))
+        }
+    }
+
+    fn long_address(&self, text: &str) -> Result<u16, String> {
+        match self.evaluate(text, 0)? {
+            value @ 0 ..= 0xFFFF => Ok(value as u16),
+            _ => Err(format!("address `{}` is out of range", text))
+        }
+    }
+
+    fn byte(&self, text: &str) -> Result<u8, String> {
+        match self.evaluate(text, 0)? {
+            value @ -0x80 ..= 0xFF => Ok(value as u8),
+            _ => Err(format!("`{}` does not fit in a byte", text))
+        }
+    }
+
+    fn nibble(&self, text: &str) -> Result<u16, String> {
+        match self.evaluate(text, 0)? {
+            value @ 0 ..= 0xF => Ok(value as u16),
+            _ => Err(format!("`{}` does not fit in a nibble", text))
+        }
+    }
+
+    /// Evaluate a sum of numbers, labels and constants.
+    fn evaluate(&self, text: &str, depth: usize) -> Result<i64, String> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(format!("`{}` is defined recursively", text));
+        }
+
+        let mut total: i64 = 0;
+        let mut sign = 1;
+        let mut expect_term = true;
+        let mut rest = text.trim();
+
+        while !rest.is_empty() {
+            if !expect_term {
+                sign = match rest.as_bytes()[0] {
+                    b'+' => 1,
+                    b'-' => -1,
+                    _ => return Err(format!("invalid expression `{}`", text))
+                };
+                rest = rest[1 ..].trim_start();
+                expect_term = true;
+                continue;
+            }
+
+            if let Some(stripped) = rest.strip_prefix('-') {
+                sign = -sign;
+                rest = stripped.trim_start();
+                continue;
+            }
+
+            let end = rest.find(|c: char| c == '+' || c == '-' || c.is_whitespace()).unwrap_or(rest.len());
+            let term = &rest[.. end];
+            let value = match parse_number(term) {
+                Some(value) => value,
+                None => match (self.labels.get(term), self.defines.get(term)) {
+                    (Some(&address), _) => address as i64,
+                    (_, Some(define)) => self.evaluate(&define.value, depth + 1).map_err(|e| {
+                        format!("{} (defined at {}:{})", e, define.file, define.number)
+                    })?,
+                    _ => return Err(format!("unknown label or constant `{}`", term))
+                }
+            };
+
+            total = value.checked_mul(sign).and_then(|value| total.checked_add(value))
+                .ok_or_else(|| format!("`{}` is too large", text))?;
+            sign = 1;
+            expect_term = false;
+            rest = rest[end ..].trim_start();
+        }
+
+        if expect_term {
+            return Err(format!("invalid expression `{}`", text));
+        }
+
+        Ok(total)
+    }
+}
+
+/// Operand of an instruction. Anything that is not a register is a value.
+enum Operand<'a> {
+    V(u16),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(&'a str),
+    Value(&'a str)
+}
+
+impl<'a> Operand<'a> {
+    fn parse(text: &'a str) -> Self {
+        match text.to_ascii_uppercase().as_str() {
+            "I"   => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT"  => Operand::Dt,
+            "ST"  => Operand::St,
+            "K"   => Operand::K,
+            "F"   => Operand::F,
+            "HF"  => Operand::Hf,
+            "B"   => Operand::B,
+            "R"   => Operand::R,
+            long if long.starts_with("LONG") && long[4 ..].starts_with(|c: char| c.is_whitespace()) =>
+                Operand::Long(text[4 ..].trim()),
+            "LONG" => Operand::Long(""),
+            name if name.len() == 2 && name.starts_with('V') => {
+                match u16::from_str_radix(&name[1 ..], 16) {
+                    Ok(x) => Operand::V(x),
+                    Err(_) => Operand::Value(text)
+                }
+            },
+            _ => Operand::Value(text)
+        }
+    }
+}
+
+fn xy(base: u16, x: u16, y: u16) -> u16 {
+    base | (x << 8) | (y << 4)
+}
+
+fn xkk(base: u16, x: u16, kk: u8) -> u16 {
+    base | (x << 8) | kk as u16
+}
+
+fn error(file: &str, line: usize, message: String) -> AssemblerError {
+    AssemblerError {
+        file: file.to_string(),
+        line,
+        message
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(';') {
+        Some(index) => &line[.. index],
+        None => line
+    }
+}
+
+fn split_operands(text: &str) -> Vec<String> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+
+    text.split(',').map(|operand| operand.trim().to_string()).collect()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' =>
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.'),
+        _ => false
+    }
+}
+
+/// Register names can't be used for labels or constants.
+fn is_reserved(name: &str) -> bool {
+    !matches!(Operand::parse(name), Operand::Value(_))
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (digits, radix) = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (hex, 16)
+    } else if let Some(hex) = text.strip_prefix('$') {
+        (hex, 16)
+    } else if let Some(binary) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        (binary, 2)
+    } else {
+        (text, 10)
+    };
+
+    i64::from_str_radix(digits, radix).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Disassembly;
+
+    #[test]
+    fn disassembled_games_assemble_to_the_same_rom() {
+        let games: [&[u8]; 4] = [
+            include_bytes!("../../resources/games/PONG"),
+            include_bytes!("../../resources/games/TETRIS"),
+            include_bytes!("../../resources/games/BRIX"),
+            include_bytes!("../../resources/games/INVADERS")
+        ];
+
+        for rom in games.iter() {
+            assert_eq!(assemble(&Disassembly::new(rom).source()).unwrap(), rom.to_vec());
+        }
+    }
+
+    #[test]
+    fn labels_can_be_used_before_they_are_defined() {
+        let source = "
+            CALL sprite_end
+            LD I, sprite + 1
+        sprite:
+            db 0x80, 0x40
+        sprite_end:
+            RET
+        ";
+
+        assert_eq!(assemble(source).unwrap(), vec![0x22, 0x06, 0xa2, 0x05, 0x80, 0x40, 0x00, 0xee]);
+    }
+
+    #[test]
+    fn includes_are_limited_in_depth() {
+        let path = std::env::temp_dir().join(format!("chip8-include-{}.c8s", std::process::id()));
+        fs::write(&path, format!("include \"{}\"\n", path.file_name().unwrap().to_str().unwrap())).unwrap();
+        let result = assemble_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap_err().message, "too many nested includes");
+    }
+
+    #[test]
+    fn literal_out_of_range_is_reported_with_its_line() {
+        let error = assemble("CLS\nLD V0, 256").unwrap_err();
+
+        assert_eq!((error.file.as_str(), error.line), ("<source>", 2));
+    }
+
+    #[test]
+    fn huge_expressions_are_errors() {
+        assert!(assemble("LD V0, 9223372036854775807 + 1").is_err());
+        assert!(assemble("LD V0, -9223372036854775807 - 2").is_err());
+    }
+}
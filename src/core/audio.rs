@@ -0,0 +1,178 @@
+use std::f32::consts::PI;
+
+/// Shape of the tone played while the sound timer is running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle
+}
+
+impl Waveform {
+    /// Names accepted by `from_name`.
+    pub const NAMES: [&'static str; 3] = ["square", "sine", "triangle"];
+
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name.to_ascii_lowercase().as_str() {
+            "square"   => Some(Waveform::Square),
+            "sine"     => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            _ => None
+        }
+    }
+
+    /// Value of the wave at a phase between 0 and 1, between -1 and 1.
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square   => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine     => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs()
+        }
+    }
+}
+
+/// Tone played by the audio backends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioSettings {
+    /// Frequency in Hz
+    pub frequency: f32,
+    /// Volume between 0 and 1
+    pub volume: f32,
+    pub waveform: Waveform
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square
+        }
+    }
+}
+
+/// XO-CHIP sound, played instead of the tone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioPattern {
+    /// 128 one-bit samples, most significant bit first, played in a loop
+    pub samples: [u8; 16],
+    /// Samples per second
+    pub rate: f32
+}
+
+impl AudioPattern {
+    /// Value of the pattern at a phase between 0 and 1, -1 or 1.
+    fn sample(&self, phase: f32) -> f32 {
+        let bit = ((phase * 128.0) as usize).min(127);
+        if self.samples[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 }
+    }
+}
+
+/// Generates the samples of the tone, or of an XO-CHIP pattern.
+pub struct Oscillator {
+    settings: AudioSettings,
+    pattern: Option<AudioPattern>,
+    sample_rate: f32,
+    // Position in the current period, between 0 and 1
+    phase: f32
+}
+
+impl Oscillator {
+    /// Create an oscillator
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The tone to generate
+    /// * `sample_rate` - Samples per second of the output
+    pub fn new(settings: AudioSettings, sample_rate: u32) -> Self {
+        Oscillator {
+            settings,
+            pattern: None,
+            sample_rate: sample_rate as f32,
+            phase: 0.0
+        }
+    }
+
+    /// Play a pattern instead of the tone, or the tone again with `None`.
+    pub fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.pattern = pattern;
+    }
+
+    /// Fill a buffer of mono samples.
+    pub fn fill(&mut self, samples: &mut [f32]) {
+        // A period is the whole pattern
+        let step = match self.pattern {
+            Some(pattern) => pattern.rate / 128.0 / self.sample_rate,
+            None => self.settings.frequency / self.sample_rate
+        };
+
+        for sample in samples.iter_mut() {
+            let value = match self.pattern {
+                Some(pattern) => pattern.sample(self.phase),
+                None => self.settings.waveform.sample(self.phase)
+            };
+            *sample = value * self.settings.volume;
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
+
+/// Something that plays the tone while the sound timer is running.
+pub trait AudioSink {
+    /// Start or stop the tone, called every frame.
+    fn set_playing(&mut self, playing: bool);
+
+    /// Play an XO-CHIP pattern instead of the tone, or the tone with
+    /// `None`. Called every frame.
+    fn set_pattern(&mut self, pattern: Option<AudioPattern>);
+
+    /// Silence the output, the tone keeps being started and stopped.
+    fn set_muted(&mut self, muted: bool);
+
+    fn is_muted(&self) -> bool;
+}
+
+/// Backend without any sound, for headless runs or when no audio device
+/// is available.
+#[derive(Default)]
+pub struct NullAudio {
+    muted: bool
+}
+
+impl NullAudio {
+    pub fn new() -> Self {
+        NullAudio {
+            muted: false
+        }
+    }
+}
+
+impl AudioSink for NullAudio {
+    fn set_playing(&mut self, _playing: bool) {}
+
+    fn set_pattern(&mut self, _pattern: Option<AudioPattern>) {}
+
+    fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    fn is_muted(&self) -> bool {
+        self.muted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_replaces_the_tone() {
+        // 1 Hz, so the 128 bits of the pattern are the first 128 samples
+        let mut oscillator = Oscillator::new(AudioSettings { volume: 1.0, ..AudioSettings::default() }, 128);
+        let mut samples = [0.0; 16];
+        oscillator.set_pattern(Some(AudioPattern { samples: [0xf0; 16], rate: 128.0 }));
+        oscillator.fill(&mut samples);
+
+        assert_eq!(&samples[.. 8], &[1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+    }
+}
@@ -0,0 +1,95 @@
+use sdl2::Sdl;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+use crate::core::audio::{AudioPattern, AudioSettings, AudioSink, Oscillator};
+
+const SAMPLE_RATE: i32 = 44100;
+
+/// Callback run by SDL on its audio thread.
+struct Tone {
+    oscillator: Oscillator,
+    muted: bool
+}
+
+impl AudioCallback for Tone {
+    type Channel = f32;
+
+    fn callback(&mut self, samples: &mut [f32]) {
+        self.oscillator.fill(samples);
+
+        if self.muted {
+            for sample in samples.iter_mut() {
+                *sample = 0.0;
+            }
+        }
+    }
+}
+
+/// Plays the tone on the SDL2 audio subsystem.
+pub struct AudioDriver {
+    device: AudioDevice<Tone>,
+    playing: bool,
+    pattern: Option<AudioPattern>,
+    muted: bool
+}
+
+impl AudioDriver {
+    /// Open the default playback device.
+    ///
+    /// Fails when there is no audio device, e.g. on a headless machine.
+    pub fn new(sdl_context: &Sdl, settings: AudioSettings) -> Result<Self, String> {
+        let audio_subsystem = sdl_context.audio()?;
+
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired, |spec| {
+            Tone {
+                oscillator: Oscillator::new(settings, spec.freq as u32),
+                muted: false
+            }
+        })?;
+
+        Ok(AudioDriver {
+            device,
+            playing: false,
+            pattern: None,
+            muted: false
+        })
+    }
+}
+
+impl AudioSink for AudioDriver {
+    fn set_playing(&mut self, playing: bool) {
+        if playing == self.playing {
+            return;
+        }
+
+        if playing {
+            self.device.resume();
+        } else {
+            self.device.pause();
+        }
+        self.playing = playing;
+    }
+
+    fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+        // Locking the audio thread only when the pattern changes
+        if pattern != self.pattern {
+            self.device.lock().oscillator.set_pattern(pattern);
+            self.pattern = pattern;
+        }
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.device.lock().muted = muted;
+        self.muted = muted;
+    }
+
+    fn is_muted(&self) -> bool {
+        self.muted
+    }
+}
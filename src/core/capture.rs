@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::core::Frame;
+use crate::core::SCHIP_HEIGHT;
+use crate::core::SCHIP_WIDTH;
+use crate::core::TIMER_FREQUENCY;
+
+/// Size of a high resolution pixel in the captures unless told otherwise,
+/// low resolution pixels being twice as large.
+pub const DEFAULT_CAPTURE_SCALE: usize = 4;
+
+// GIF layout
+//
+// "GIF89a", the size of the image, the NETSCAPE2.0 extension looping the
+// animation, then for every frame: a graphic control extension with the
+// time it is shown, an image descriptor, a colour table of the colours of
+// the frame and its pixels compressed with LZW. Little-endian.
+
+const GIF_HEADER: &[u8; 6] = b"GIF89a";
+const GIF_TRAILER: u8 = 0x3b;
+
+// LZW codes are at most 12 bits long
+const MAX_CODES: u16 = 4096;
+
+// GIF delays are in hundredths of a second
+const CENTISECONDS: u64 = 100;
+
+// Y4M layout
+//
+// A line of text with the size and the frame rate, then every frame as
+// "FRAME\n" followed by its Y, Cb and Cr planes, at full resolution (4:4:4).
+
+/// File format of a capture.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CaptureFormat {
+    /// Animated GIF, frames that don't change are merged
+    #[default]
+    Gif,
+    /// Uncompressed YUV4MPEG2 video, for video editors and encoders
+    Y4m
+}
+
+impl CaptureFormat {
+    /// Names accepted by `from_name`, also the file extensions.
+    pub const NAMES: [&'static str; 2] = ["gif", "y4m"];
+
+    pub fn from_name(name: &str) -> Option<CaptureFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "gif" => Some(CaptureFormat::Gif),
+            "y4m" => Some(CaptureFormat::Y4m),
+            _ => None
+        }
+    }
+
+    /// Format given by the extension of a file.
+    pub fn from_path(path: &str) -> Option<CaptureFormat> {
+        Path::new(path).extension().and_then(|extension| CaptureFormat::from_name(extension.to_str()?))
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            CaptureFormat::Gif => "gif",
+            CaptureFormat::Y4m => "y4m"
+        }
+    }
+
+    /// Create a sink writing this format.
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - Where the capture is written
+    /// * `scale` - Size of a high resolution pixel in the capture
+    pub fn sink<W: Write + 'static>(self, output: W, scale: usize) -> io::Result<Box<dyn FrameSink>> {
+        Ok(match self {
+            CaptureFormat::Gif => Box::new(GifCapture::new(output, scale)?),
+            CaptureFormat::Y4m => Box::new(Y4mCapture::new(output, scale)?)
+        })
+    }
+}
+
+/// Destination of the frames shown, one per 60 Hz frame.
+pub trait FrameSink {
+    fn frame(&mut self, frame: &Frame) -> io::Result<()>;
+
+    /// Write what is left once the last frame has been given.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the frames as an animated GIF.
+///
+/// A frame is only written once the next one differs, so that the time it
+/// is shown can be lengthened instead of repeating it.
+pub struct GifCapture<W: Write> {
+    output: W,
+    width: usize,
+    // Frame waiting for a different one, and its number
+    pending: Option<(Frame, u64)>,
+    frames: u64
+}
+
+impl<W: Write> GifCapture<W> {
+    /// Create the sink and write the header.
+    pub fn new(mut output: W, scale: usize) -> io::Result<Self> {
+        let (width, height) = capture_size(scale);
+
+        output.write_all(GIF_HEADER)?;
+        output.write_all(&(width as u16).to_le_bytes())?;
+        output.write_all(&(height as u16).to_le_bytes())?;
+        // No global colour table, every frame has its own
+        output.write_all(&[0, 0, 0])?;
+        // Loop forever
+        output.write_all(&[0x21, 0xff, 0x0b])?;
+        output.write_all(b"NETSCAPE2.0")?;
+        output.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+
+        Ok(GifCapture {
+            output,
+            width,
+            pending: None,
+            frames: 0
+        })
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        let (frame, start) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(())
+        };
+
+        // Rounding the time of every frame keeps 60 fps on average with
+        // delays of 1 or 2 hundredths
+        let time = |frame: u64| (frame * CENTISECONDS + TIMER_FREQUENCY as u64 / 2) / TIMER_FREQUENCY as u64;
+        let delay = (time(self.frames) - time(start)).min(u16::MAX as u64) as u16;
+
+        let (colors, indices) = index_colors(&frame.pixels);
+        // The colour table has 2 to 256 entries, a power of 2
+        let bits = (1 ..= 8).find(|bits| colors.len() <= 1 << bits).unwrap_or(8);
+
+        // Graphic control extension: the frame stays under the next one
+        self.output.write_all(&[0x21, 0xf9, 0x04, 0x04])?;
+        self.output.write_all(&delay.to_le_bytes())?;
+        self.output.write_all(&[0x00, 0x00])?;
+
+        // Image descriptor, with a local colour table
+        self.output.write_all(&[0x2c, 0x00, 0x00, 0x00, 0x00])?;
+        self.output.write_all(&(frame.width as u16).to_le_bytes())?;
+        self.output.write_all(&(frame.height as u16).to_le_bytes())?;
+        self.output.write_all(&[0x80 | (bits - 1)])?;
+        for i in 0 .. 1 << bits {
+            self.output.write_all(colors.get(i).unwrap_or(&[0, 0, 0]))?;
+        }
+
+        let min_code_size = bits.max(2);
+        self.output.write_all(&[min_code_size])?;
+        for block in lzw(&indices, min_code_size).chunks(255) {
+            self.output.write_all(&[block.len() as u8])?;
+            self.output.write_all(block)?;
+        }
+        self.output.write_all(&[0x00])
+    }
+}
+
+impl<W: Write> FrameSink for GifCapture<W> {
+    fn frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let frame = fit(frame, self.width);
+        if !self.pending.as_ref().is_some_and(|(pending, _)| *pending == frame) {
+            self.write_pending()?;
+            self.pending = Some((frame, self.frames));
+        }
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.output.write_all(&[GIF_TRAILER])?;
+        self.output.flush()
+    }
+}
+
+/// Writes the frames as an uncompressed YUV4MPEG2 video at 60 fps.
+pub struct Y4mCapture<W: Write> {
+    output: W,
+    width: usize
+}
+
+impl<W: Write> Y4mCapture<W> {
+    /// Create the sink and write the header.
+    pub fn new(mut output: W, scale: usize) -> io::Result<Self> {
+        let (width, height) = capture_size(scale);
+        writeln!(output, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, TIMER_FREQUENCY)?;
+
+        Ok(Y4mCapture {
+            output,
+            width
+        })
+    }
+}
+
+impl<W: Write> FrameSink for Y4mCapture<W> {
+    fn frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let frame = fit(frame, self.width);
+
+        // BT.601 with the studio range, what players expect without
+        // being told otherwise
+        let mut planes: Vec<Vec<u8>> = (0 .. 3).map(|_| Vec::with_capacity(frame.pixels.len() / 3)).collect();
+        for pixel in frame.pixels.chunks(3) {
+            let (r, g, b) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+            planes[0].push((16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0).round() as u8);
+            planes[1].push((128.0 + (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0).round() as u8);
+            planes[2].push((128.0 + (112.439 * r - 94.154 * g - 18.285 * b) / 256.0).round() as u8);
+        }
+
+        self.output.write_all(b"FRAME\n")?;
+        for plane in planes.iter() {
+            self.output.write_all(plane)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Width and height of the captures, the 128x64 display scaled.
+fn capture_size(scale: usize) -> (usize, usize) {
+    let scale = scale.max(1);
+    (SCHIP_WIDTH * scale, SCHIP_HEIGHT * scale)
+}
+
+/// Scale a 64x32 or 128x64 frame to the width of the capture.
+fn fit(frame: &Frame, width: usize) -> Frame {
+    frame.scaled(width / frame.width.max(1))
+}
+
+/// Colours of a frame and the index of every pixel in them.
+///
+/// The filters can make more than the 256 colours a GIF frame can have,
+/// the colours are then reduced to 3 bits of red and green and 2 of blue.
+fn index_colors(pixels: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut colors: Vec<[u8; 3]> = Vec::new();
+    let mut table: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut indices = Vec::with_capacity(pixels.len() / 3);
+
+    for pixel in pixels.chunks(3) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = match table.get(&color) {
+            Some(&index) => index,
+            None if colors.len() < 256 => {
+                table.insert(color, colors.len() as u8);
+                colors.push(color);
+                (colors.len() - 1) as u8
+            },
+            None => {
+                let reduced: Vec<u8> = pixels.iter().enumerate()
+                    .map(|(i, &component)| if i % 3 == 2 { component & 0xc0 } else { component & 0xe0 })
+                    .collect();
+                return index_colors(&reduced);
+            }
+        };
+        indices.push(index);
+    }
+
+    (colors, indices)
+}
+
+/// Compress the colour indices of a frame with the variable-length LZW
+/// of GIF.
+fn lzw(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut bits = CodeWriter::default();
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = min_code_size as u32 + 1;
+
+    bits.write(clear, size);
+    let mut prefix = match indices.first() {
+        Some(&index) => index as u16,
+        None => {
+            bits.write(end, size);
+            return bits.finish();
+        }
+    };
+
+    for &index in indices[1 ..].iter() {
+        if let Some(&code) = codes.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        bits.write(prefix, size);
+        if next < MAX_CODES {
+            codes.insert((prefix, index), next);
+            next += 1;
+            if next > 1 << size {
+                size += 1;
+            }
+        } else {
+            // The table is full, start a new one
+            bits.write(clear, size);
+            codes.clear();
+            next = end + 1;
+            size = min_code_size as u32 + 1;
+        }
+        prefix = index as u16;
+    }
+
+    bits.write(prefix, size);
+    bits.write(end, size);
+
+    bits.finish()
+}
+
+/// Packs LZW codes, least significant bit first.
+#[derive(Default)]
+struct CodeWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32
+}
+
+impl CodeWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // GIF decoder of the LZW codes
+    fn unlzw(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let reset = || -> Vec<Vec<u8>> { (0 .. end + 1).map(|code| vec![code as u8]).collect() };
+
+        let mut table = reset();
+        let mut size = min_code_size as usize + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut indices = Vec::new();
+        let mut position = 0;
+        loop {
+            let code = (0 .. size).fold(0, |code, bit| {
+                let offset = position + bit;
+                code | (((data[offset / 8] >> (offset % 8)) & 1) as usize) << bit
+            });
+            position += size;
+
+            if code == clear {
+                table = reset();
+                size = min_code_size as usize + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return indices;
+            }
+
+            let entry = match (table.get(code), previous.as_ref()) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => [previous.as_slice(), &previous[.. 1]].concat(),
+                (None, None) => panic!("code {} without a previous one", code)
+            };
+            indices.extend_from_slice(&entry);
+            if let Some(previous) = previous.take() {
+                if table.len() < MAX_CODES as usize {
+                    table.push([previous.as_slice(), &entry[.. 1]].concat());
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            previous = Some(entry);
+        }
+    }
+
+    // Pseudo-random bytes, compressing badly so that the table fills up
+    fn noise(len: usize, modulo: u32) -> Vec<u8> {
+        let mut seed = 12345u32;
+        (0 .. len).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            ((seed >> 16) % modulo) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        for (indices, min_code_size) in [
+            (vec![], 2),
+            (vec![1, 1, 1, 1, 0, 0, 1, 1, 1, 1, 0, 0, 1, 1, 1], 2),
+            (noise(1000, 4), 2)
+        ].iter() {
+            assert_eq!(unlzw(&lzw(indices, *min_code_size), *min_code_size), *indices);
+        }
+    }
+
+    #[test]
+    fn lzw_starts_a_new_table_once_full() {
+        // Far more than 4096 different strings
+        let indices = noise(50_000, 256);
+        let compressed = lzw(&indices, 8);
+
+        assert_eq!(unlzw(&compressed, 8), indices);
+    }
+
+    #[test]
+    fn colors_are_indexed() {
+        let pixels = [[0, 0, 0], [255, 255, 255], [0, 0, 0], [10, 20, 30]].concat();
+
+        let (colors, indices) = index_colors(&pixels);
+        assert_eq!(colors, vec![[0, 0, 0], [255, 255, 255], [10, 20, 30]]);
+        assert_eq!(indices, vec![0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn more_than_256_colors_are_reduced() {
+        let pixels: Vec<u8> = (0 .. 300u32).flat_map(|i| vec![i as u8, (i >> 8) as u8 * 0x40, 0xff]).collect();
+
+        let (colors, indices) = index_colors(&pixels);
+        assert!(colors.len() <= 256);
+        for (pixel, &index) in pixels.chunks(3).zip(indices.iter()) {
+            assert_eq!(colors[index as usize], [pixel[0] & 0xe0, pixel[1] & 0xe0, pixel[2] & 0xc0]);
+        }
+    }
+
+    #[test]
+    fn y4m_header_and_frames() {
+        let mut capture = Y4mCapture::new(Vec::new(), 2).unwrap();
+        let header = b"YUV4MPEG2 W256 H128 F60:1 Ip A1:1 C444\n";
+        assert_eq!(capture.output, header.to_vec());
+
+        // A low resolution frame is scaled to the size of the capture
+        let mut pixels = vec![0; 64 * 32 * 3];
+        pixels[.. 3].copy_from_slice(&[255, 255, 255]);
+        capture.frame(&Frame { width: 64, height: 32, pixels }).unwrap();
+        capture.finish().unwrap();
+
+        let plane = 256 * 128;
+        let frame = &capture.output[header.len() ..];
+        assert_eq!(frame.len(), 6 + 3 * plane);
+        assert_eq!(&frame[.. 6], b"FRAME\n");
+
+        let y = &frame[6 .. 6 + plane];
+        // The white pixel is 4x4 in the capture, black is 16 and white 235
+        assert_eq!((y[0], y[3], y[4], y[256 * 3], y[256 * 4]), (235, 235, 16, 235, 16));
+        assert!(frame[6 + plane ..].iter().all(|&chroma| chroma == 128));
+    }
+}
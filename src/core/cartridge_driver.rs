@@ -1,13 +1,14 @@
 use std::io;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
 
 pub struct CartridgeDriver {
     cartridge_content: Vec<u8>
 }
 
 impl CartridgeDriver {
-    pub fn new(filename: &String) -> Result<Self, io::Error> {
+    pub fn new<P: AsRef<Path>>(filename: P) -> Result<Self, io::Error> {
         let mut buffer = Vec::new();
         let mut file = File::open(filename)?;
 
@@ -1,2 +1,6 @@
 pub const CHIP8_WIDTH:  usize = 64;
 pub const CHIP8_HEIGHT: usize = 32;
+
+// SUPER-CHIP high resolution mode
+pub const SCHIP_WIDTH:  usize = 128;
+pub const SCHIP_HEIGHT: usize = 64;
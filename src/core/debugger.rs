@@ -0,0 +1,616 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::core::Processor;
+use crate::core::mnemonic_at;
+
+const OPCODE_CALL:     u16 = 0x2000;
+const OPCODE_MASK:     u16 = 0xF000;
+const OPCODE_SIZE:     u16 = 2;
+
+const HELP: &str = "\
+Execution
+  c, continue             Resume execution
+  p, pause                Pause execution
+  s, step [n]             Execute n instructions (default 1)
+  n, next                 Step over a CALL
+  f, finish               Run until the current subroutine returns
+Breakpoints
+  b, break <addr>         Break when PC reaches addr
+  w, watch <addr>         Break when memory at addr is written
+  cond <reg> <op> <value> Break when the condition becomes true, e.g. `cond V3 == 0x1f`
+  d, delete <id>          Delete a breakpoint, watchpoint or condition (see `info`)
+  info                    List breakpoints, watchpoints and conditions
+Inspection
+  r, regs                 Show registers and timers
+  x, mem <addr> [len]     Dump memory
+  bt, stack               Show the call stack
+  set <reg> <value>       Change V0-VF, I, PC, DT or ST
+  poke <addr> <byte>..    Write bytes into memory
+  h, help                 Show this message";
+
+/// Register that can be inspected or changed from the debugger.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Dt,
+    St
+}
+
+impl Register {
+    fn parse(name: &str) -> Option<Register> {
+        let name = name.to_ascii_uppercase();
+        match name.as_str() {
+            "I"  => Some(Register::I),
+            "PC" => Some(Register::Pc),
+            "DT" => Some(Register::Dt),
+            "ST" => Some(Register::St),
+            _ if name.len() == 2 && name.starts_with('V') =>
+                u8::from_str_radix(&name[1..], 16).ok().map(Register::V),
+            _    => None
+        }
+    }
+
+    fn read(self, processor: &Processor) -> u16 {
+        match self {
+            Register::V(x) => processor.v(x) as u16,
+            Register::I    => processor.i(),
+            Register::Pc   => processor.pc(),
+            Register::Dt   => processor.delay_timer() as u16,
+            Register::St   => processor.sound_timer() as u16
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I    => write!(f, "I"),
+            Register::Pc   => write!(f, "PC"),
+            Register::Dt   => write!(f, "DT"),
+            Register::St   => write!(f, "ST")
+        }
+    }
+}
+
+/// Comparison used by a register condition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+impl Comparison {
+    fn parse(op: &str) -> Option<Comparison> {
+        match op {
+            "==" => Some(Comparison::Eq),
+            "!=" => Some(Comparison::Ne),
+            "<"  => Some(Comparison::Lt),
+            "<=" => Some(Comparison::Le),
+            ">"  => Some(Comparison::Gt),
+            ">=" => Some(Comparison::Ge),
+            _    => None
+        }
+    }
+
+    fn eval(self, lhs: u16, rhs: u16) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs <  rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs >  rhs,
+            Comparison::Ge => lhs >= rhs
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">="
+        };
+
+        write!(f, "{}", op)
+    }
+}
+
+/// Breaks when a register comparison goes from false to true.
+#[derive(Clone, Debug)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+    // Result of the last evaluation, used to detect the transition
+    last: bool
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {:#x}", self.register, self.comparison, self.value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Running,
+    Paused,
+    // Number of instructions left to execute
+    Stepping(u32),
+    // Run until the call returns to this address with this stack depth
+    StepOver { pc: u16, sp: u16 },
+    // Run until the stack depth goes below this one
+    StepOut { sp: u16 }
+}
+
+/// Step debugger driving a `Processor`.
+///
+/// The front-end asks `is_running` before every tick, calls `after_tick`
+/// after it, and forwards the lines typed on the console to `execute`.
+pub struct Debugger {
+    mode: Mode,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<u16>,
+    conditions: Vec<Condition>
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    /// Creates a debugger with the execution paused.
+    pub fn new() -> Self {
+        Debugger {
+            mode: Mode::Paused,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            conditions: Vec::new()
+        }
+    }
+
+    /// Whether the processor should execute the next instruction.
+    pub fn is_running(&self) -> bool {
+        self.mode != Mode::Paused
+    }
+
+    /// Stop the execution, e.g. after a fault.
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+    }
+
+    /// Check the state of the processor after a tick. Returns a description
+    /// of the event if execution has been paused.
+    ///
+    /// Ticks spent waiting for a key or the vertical blank did not execute
+    /// anything: they neither count as steps nor hit breakpoints again.
+    pub fn after_tick(&mut self, processor: &Processor, executed: bool) -> Option<String> {
+        if !executed {
+            return None;
+        }
+
+        let mut reason = None;
+
+        for &address in processor.memory_writes() {
+            if self.watchpoints.contains(&address) {
+                reason = Some(format!("Watchpoint: {:#05x} = {:#04x}",
+                                      address, processor.memory()[address as usize]));
+            }
+        }
+
+        for condition in self.conditions.iter_mut() {
+            let value = condition.register.read(processor);
+            let result = condition.comparison.eval(value, condition.value);
+            if result && !condition.last {
+                reason = Some(format!("Condition: {} ({} = {:#x})", condition, condition.register, value));
+            }
+            condition.last = result;
+        }
+
+        if reason.is_none() && self.breakpoints.contains(&processor.pc()) {
+            reason = Some("Breakpoint".to_string());
+        }
+
+        if reason.is_none() {
+            reason = match self.mode {
+                Mode::Stepping(1) => Some(String::new()),
+                Mode::Stepping(n) => {
+                    self.mode = Mode::Stepping(n - 1);
+                    None
+                },
+                Mode::StepOver { pc, sp } if processor.pc() == pc && processor.sp() == sp =>
+                    Some(String::new()),
+                Mode::StepOut { sp } if processor.sp() < sp =>
+                    Some(String::new()),
+                _ => None
+            };
+        }
+
+        reason.map(|reason| {
+            self.mode = Mode::Paused;
+            if reason.is_empty() {
+                self.location(processor)
+            } else {
+                format!("{} at {}", reason, self.location(processor))
+            }
+        })
+    }
+
+    /// Execute a console command. Returns the text to display.
+    pub fn execute(&mut self, processor: &mut Processor, line: &str) -> String {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() {
+            return String::new();
+        }
+
+        match self.execute_command(processor, &args) {
+            Ok(message) => message,
+            Err(message) => format!("Error: {}", message)
+        }
+    }
+
+    fn execute_command(&mut self, processor: &mut Processor, args: &[&str]) -> Result<String, String> {
+        match args[0] {
+            "c" | "continue" => {
+                self.mode = Mode::Running;
+                Ok("Running".to_string())
+            },
+            "p" | "pause" => {
+                self.mode = Mode::Paused;
+                Ok(self.location(processor))
+            },
+            "s" | "step" => {
+                let count = match args.get(1) {
+                    Some(count) => count.parse().map_err(|_| format!("invalid count `{}`", count))?,
+                    None => 1
+                };
+                if count > 0 {
+                    self.mode = Mode::Stepping(count);
+                }
+                Ok(String::new())
+            },
+            "n" | "next" => {
+                self.mode = if processor.opcode() & OPCODE_MASK == OPCODE_CALL {
+                    Mode::StepOver { pc: processor.pc().wrapping_add(OPCODE_SIZE), sp: processor.sp() }
+                } else {
+                    Mode::Stepping(1)
+                };
+                Ok(String::new())
+            },
+            "f" | "finish" => {
+                if processor.sp() == 0 {
+                    return Err("not inside a subroutine".to_string());
+                }
+                self.mode = Mode::StepOut { sp: processor.sp() };
+                Ok(String::new())
+            },
+            "b" | "break" => {
+                let address = parse_address(processor, args.get(1))?;
+                self.breakpoints.insert(address);
+                Ok(format!("Breakpoint at {:#05x}", address))
+            },
+            "w" | "watch" => {
+                let address = parse_address(processor, args.get(1))?;
+                self.watchpoints.insert(address);
+                Ok(format!("Watchpoint at {:#05x}", address))
+            },
+            "cond" => {
+                if args.len() != 4 {
+                    return Err("usage: cond <reg> <op> <value>".to_string());
+                }
+                let register = Register::parse(args[1])
+                    .ok_or_else(|| format!("unknown register `{}`", args[1]))?;
+                let comparison = Comparison::parse(args[2])
+                    .ok_or_else(|| format!("unknown comparison `{}`", args[2]))?;
+                let value = parse_number(args[3])?;
+                let last = comparison.eval(register.read(processor), value);
+                let condition = Condition { register, comparison, value, last };
+                let message = format!("Condition {}", condition);
+                self.conditions.push(condition);
+                Ok(message)
+            },
+            "d" | "delete" => self.delete(args.get(1)),
+            "info" => Ok(self.info()),
+            "r" | "regs" => Ok(registers(processor)),
+            "x" | "mem" => {
+                let address = parse_address(processor, args.get(1))?;
+                let len = match args.get(2) {
+                    Some(len) => parse_number(len)? as usize,
+                    None => 64
+                };
+                Ok(dump_memory(processor.memory(), address as usize, len))
+            },
+            "bt" | "stack" => {
+                let mut text = String::new();
+                for (depth, address) in processor.stack().iter().enumerate().rev() {
+                    let _ = writeln!(text, "#{} {:#05x}", depth, address);
+                }
+                text.push_str(&format!("PC {:#05x}", processor.pc()));
+                Ok(text)
+            },
+            "set" => {
+                if args.len() != 3 {
+                    return Err("usage: set <reg> <value>".to_string());
+                }
+                let register = Register::parse(args[1])
+                    .ok_or_else(|| format!("unknown register `{}`", args[1]))?;
+                let value = match register {
+                    Register::Pc => parse_address(processor, args.get(2))?,
+                    _            => parse_number(args[2])?
+                };
+                match register {
+                    Register::V(x) => processor.set_v(x, value as u8),
+                    Register::I    => processor.set_i(value),
+                    Register::Pc   => processor.set_pc(value),
+                    Register::Dt   => processor.set_delay_timer(value as u8),
+                    Register::St   => processor.set_sound_timer(value as u8)
+                }
+                Ok(format!("{} = {:#x}", register, register.read(processor)))
+            },
+            "poke" => {
+                let address = parse_address(processor, args.get(1))?;
+                if args.len() < 3 {
+                    return Err("usage: poke <addr> <byte>..".to_string());
+                }
+                for (offset, byte) in args[2..].iter().enumerate() {
+                    let target = address as usize + offset;
+                    if target >= processor.memory().len() {
+                        return Err(format!("address {:#x} is out of memory", target));
+                    }
+                    processor.poke(target as u16, parse_number(byte)? as u8);
+                }
+                Ok(dump_memory(processor.memory(), address as usize, args.len() - 2))
+            },
+            "h" | "help" => Ok(HELP.to_string()),
+            command => Err(format!("unknown command `{}`, type `help`", command))
+        }
+    }
+
+    fn delete(&mut self, id: Option<&&str>) -> Result<String, String> {
+        let id = id.ok_or("usage: delete <id>")?;
+        // The kind is the first character, which may not be ASCII
+        let (kind, index) = id.split_at(id.chars().next().map_or(0, char::len_utf8));
+        let index: usize = index.parse().map_err(|_| format!("invalid id `{}`", id))?;
+
+        let removed = match kind {
+            "b" => nth(&self.breakpoints, index).map(|address| self.breakpoints.remove(&address)),
+            "w" => nth(&self.watchpoints, index).map(|address| self.watchpoints.remove(&address)),
+            "c" if index < self.conditions.len() => {
+                self.conditions.remove(index);
+                Some(true)
+            },
+            _ => None
+        };
+
+        if removed.is_none() {
+            return Err(format!("no breakpoint `{}`", id));
+        }
+
+        Ok(format!("Deleted {}", id))
+    }
+
+    fn info(&self) -> String {
+        let mut text = String::new();
+        for (index, address) in self.breakpoints.iter().enumerate() {
+            let _ = writeln!(text, "b{}  break {:#05x}", index, address);
+        }
+        for (index, address) in self.watchpoints.iter().enumerate() {
+            let _ = writeln!(text, "w{}  watch {:#05x}", index, address);
+        }
+        for (index, condition) in self.conditions.iter().enumerate() {
+            let _ = writeln!(text, "c{}  cond  {}", index, condition);
+        }
+        if text.is_empty() {
+            text.push_str("No breakpoints");
+        }
+
+        text.trim_end().to_string()
+    }
+
+    fn location(&self, processor: &Processor) -> String {
+        let opcode = processor.opcode();
+        let instruction = mnemonic_at(processor.memory(), processor.pc() as usize)
+            .unwrap_or_else(|| "???".to_string());
+
+        format!("PC = {:#05x}  Opcode = {:04x}  {}", processor.pc(), opcode, instruction)
+    }
+}
+
+fn nth(set: &BTreeSet<u16>, index: usize) -> Option<u16> {
+    set.iter().nth(index).cloned()
+}
+
+/// Parse a number, hexadecimal when prefixed by `0x` or `$`.
+fn parse_number(text: &str) -> Result<u16, String> {
+    let result = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16)
+    } else {
+        text.parse()
+    };
+
+    result.map_err(|_| format!("invalid number `{}`", text))
+}
+
+fn parse_address(processor: &Processor, text: Option<&&str>) -> Result<u16, String> {
+    let text = text.ok_or("missing address")?;
+    let address = parse_number(text)?;
+    if address as usize >= processor.memory().len() {
+        return Err(format!("address {:#x} is out of memory", address));
+    }
+
+    Ok(address)
+}
+
+fn registers(processor: &Processor) -> String {
+    let mut text = String::new();
+    for x in 0 .. 16 {
+        match x {
+            0 => {},
+            8 => text.push('\n'),
+            _ => text.push(' ')
+        }
+        let _ = write!(text, "V{:X}={:02x}", x, processor.v(x));
+    }
+    let _ = write!(text, "\nI={:03x} PC={:03x} SP={:x} DT={:02x} ST={:02x}",
+                   processor.i(), processor.pc(), processor.sp(),
+                   processor.delay_timer(), processor.sound_timer());
+
+    text
+}
+
+fn dump_memory(memory: &[u8], address: usize, len: usize) -> String {
+    let end = (address + len).min(memory.len());
+    let mut text = String::new();
+    for (row, chunk) in memory[address .. end].chunks(16).enumerate() {
+        let _ = write!(text, "{:03x}:", address + row * 16);
+        for byte in chunk {
+            let _ = write!(text, " {:02x}", byte);
+        }
+        text.push('\n');
+    }
+
+    text.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{KeyEvent, Quirks, Variant};
+
+    // Processor loaded with a program, and a debugger that runs it after
+    // executing the commands
+    fn start(program: &[u8], commands: &[&str]) -> (Debugger, Processor) {
+        let mut debugger = Debugger::new();
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+        processor.load(program).unwrap();
+        for command in commands {
+            debugger.execute(&mut processor, command);
+        }
+
+        (debugger, processor)
+    }
+
+    // Run until the debugger pauses, at most `ticks` ticks. Returns the
+    // event and the number of ticks it took.
+    fn run(debugger: &mut Debugger, processor: &mut Processor, ticks: usize) -> Option<(String, usize)> {
+        for tick in 1 ..= ticks {
+            let output = processor.tick([false; 16]).unwrap();
+            if let Some(event) = debugger.after_tick(processor, output.executed) {
+                return Some((event, tick));
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn delete_rejects_a_non_ascii_id() {
+        let mut debugger = Debugger::new();
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+
+        assert_eq!(debugger.execute(&mut processor, "delete é1"), "Error: no breakpoint `é1`");
+    }
+
+    #[test]
+    fn next_over_a_call_at_the_end_of_memory() {
+        let mut debugger = Debugger::new();
+        let mut processor = Processor::new(Variant::XoChip, Quirks::default());
+        // CALL 200
+        processor.poke(0xfffe, 0x22);
+        processor.poke(0xffff, 0x00);
+        processor.set_pc(0xfffe);
+
+        assert_eq!(debugger.execute(&mut processor, "next"), "");
+    }
+
+    #[test]
+    fn breakpoint() {
+        // LD V0, 01 / LD V1, 02 / LD V2, 03 / JP 206
+        let program = [0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x06];
+        let (mut debugger, mut processor) = start(&program, &["break 0x204", "continue"]);
+
+        let (event, ticks) = run(&mut debugger, &mut processor, 10).unwrap();
+        assert_eq!(event, "Breakpoint at PC = 0x204  Opcode = 6203  LD V2, 0x03");
+        assert_eq!(ticks, 2);
+        assert!(!debugger.is_running());
+    }
+
+    #[test]
+    fn watchpoint() {
+        // LD I, 300 / LD V0, 05 / LD [I], V0 / JP 206
+        let program = [0xa3, 0x00, 0x60, 0x05, 0xf0, 0x55, 0x12, 0x06];
+        let (mut debugger, mut processor) = start(&program, &["watch 0x301", "watch 0x300", "continue"]);
+
+        let (event, ticks) = run(&mut debugger, &mut processor, 10).unwrap();
+        assert!(event.starts_with("Watchpoint: 0x300 = 0x05 at PC = 0x206"), "{}", event);
+        assert_eq!(ticks, 3);
+    }
+
+    #[test]
+    fn condition_breaks_when_it_becomes_true() {
+        // ADD V3, 01 / JP 200
+        let program = [0x73, 0x01, 0x12, 0x00];
+        let (mut debugger, mut processor) = start(&program, &["cond V3 >= 3", "continue"]);
+
+        let (event, ticks) = run(&mut debugger, &mut processor, 20).unwrap();
+        assert!(event.starts_with("Condition: V3 >= 0x3 (V3 = 0x3)"), "{}", event);
+        assert_eq!(ticks, 5);
+
+        // Still true, but no longer a transition
+        debugger.execute(&mut processor, "continue");
+        assert_eq!(run(&mut debugger, &mut processor, 20), None);
+    }
+
+    // CALL 206 / LD V0, 01 / JP 204 / LD V1, 02 / RET
+    const SUBROUTINE: [u8; 10] = [0x22, 0x06, 0x60, 0x01, 0x12, 0x04, 0x61, 0x02, 0x00, 0xee];
+
+    #[test]
+    fn next_steps_over_a_call() {
+        let (mut debugger, mut processor) = start(&SUBROUTINE, &["next"]);
+
+        let (_, ticks) = run(&mut debugger, &mut processor, 10).unwrap();
+        assert_eq!((ticks, processor.pc(), processor.sp()), (3, 0x202, 0));
+        assert_eq!(processor.v(1), 0x02);
+    }
+
+    #[test]
+    fn finish_runs_until_the_subroutine_returns() {
+        let (mut debugger, mut processor) = start(&SUBROUTINE, &["step"]);
+        run(&mut debugger, &mut processor, 1).unwrap();
+        assert_eq!(debugger.execute(&mut processor, "finish"), "");
+
+        let (_, ticks) = run(&mut debugger, &mut processor, 10).unwrap();
+        assert_eq!((ticks, processor.pc(), processor.sp()), (2, 0x202, 0));
+    }
+
+    #[test]
+    fn steps_count_executed_instructions_only() {
+        // LD V0, K / LD V1, 01 / LD V2, 02
+        let (mut debugger, mut processor) = start(&[0xf0, 0x0a, 0x61, 0x01, 0x62, 0x02], &["step 2"]);
+
+        // Waiting for the key executes nothing
+        assert_eq!(run(&mut debugger, &mut processor, 5), None);
+        assert!(debugger.is_running());
+
+        processor.key_event(KeyEvent { key: 4, pressed: true });
+        processor.key_event(KeyEvent { key: 4, pressed: false });
+        let (event, ticks) = run(&mut debugger, &mut processor, 5).unwrap();
+        assert_eq!(event, "PC = 0x204  Opcode = 6202  LD V2, 0x02");
+        assert_eq!(ticks, 1);
+    }
+}
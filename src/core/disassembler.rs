@@ -0,0 +1,390 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+const PROGRAM_START: u16 = 0x200;
+const OPCODE_SIZE:   u16 = 2;
+
+/// Decode an opcode into its mnemonic, e.g. `LD V3, 0x1F`.
+///
+/// Returns `None` if the opcode is not a valid instruction. The XO-CHIP
+/// `F000 nnnn` instruction needs the word that follows it, use
+/// `mnemonic_at` to decode it.
+pub fn mnemonic(opcode: u16) -> Option<String> {
+    format_instruction(opcode, None, &|nnn| format!("0x{:03X}", nnn))
+}
+
+/// Decode the instruction stored at an address of the memory.
+pub fn mnemonic_at(memory: &[u8], address: usize) -> Option<String> {
+    let word = |address: usize| {
+        Some(((*memory.get(address)? as u16) << 8) | *memory.get(address + 1)? as u16)
+    };
+
+    format_instruction(word(address)?, word(address + 2), &|nnn| format!("0x{:03X}", nnn))
+}
+
+/// Size of an instruction in bytes.
+fn instruction_size(opcode: u16) -> u16 {
+    if opcode == 0xF000 { 2 * OPCODE_SIZE } else { OPCODE_SIZE }
+}
+
+fn format_instruction(opcode: u16, next: Option<u16>, address: &dyn Fn(u16) -> String) -> Option<String> {
+    let nibbles = (
+        (opcode & 0xF000) >> 12,
+        (opcode & 0x0F00) >> 8,
+        (opcode & 0x00F0) >> 4,
+         opcode & 0x000F
+    );
+
+    let x   = nibbles.1;
+    let y   = nibbles.2;
+    let n   = nibbles.3;
+    let kk  = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    let text = match nibbles {
+        (0x0,0x0,0xe,0x0) => "CLS".to_string(),
+        (0x0,0x0,0xe,0xe) => "RET".to_string(),
+        (0x0,0x0,0xc,_)   => format!("SCD {}", n),
+        (0x0,0x0,0xd,_)   => format!("SCU {}", n),
+        (0x0,0x0,0xf,0xb) => "SCR".to_string(),
+        (0x0,0x0,0xf,0xc) => "SCL".to_string(),
+        (0x0,0x0,0xf,0xd) => "EXIT".to_string(),
+        (0x0,0x0,0xf,0xe) => "LOW".to_string(),
+        (0x0,0x0,0xf,0xf) => "HIGH".to_string(),
+        (0x0,_,_,_)       => format!("SYS {}", address(nnn)),
+        (0x1,_,_,_)       => format!("JP {}", address(nnn)),
+        (0x2,_,_,_)       => format!("CALL {}", address(nnn)),
+        (0x3,_,_,_)       => format!("SE V{:X}, 0x{:02X}", x, kk),
+        (0x4,_,_,_)       => format!("SNE V{:X}, 0x{:02X}", x, kk),
+        (0x5,_,_,0x0)     => format!("SE V{:X}, V{:X}", x, y),
+        (0x5,_,_,0x2)     => format!("SAVE V{:X}, V{:X}", x, y),
+        (0x5,_,_,0x3)     => format!("LOAD V{:X}, V{:X}", x, y),
+        (0x6,_,_,_)       => format!("LD V{:X}, 0x{:02X}", x, kk),
+        (0x7,_,_,_)       => format!("ADD V{:X}, 0x{:02X}", x, kk),
+        (0x8,_,_,0x0)     => format!("LD V{:X}, V{:X}", x, y),
+        (0x8,_,_,0x1)     => format!("OR V{:X}, V{:X}", x, y),
+        (0x8,_,_,0x2)     => format!("AND V{:X}, V{:X}", x, y),
+        (0x8,_,_,0x3)     => format!("XOR V{:X}, V{:X}", x, y),
+        (0x8,_,_,0x4)     => format!("ADD V{:X}, V{:X}", x, y),
+        (0x8,_,_,0x5)     => format!("SUB V{:X}, V{:X}", x, y),
+        (0x8,_,_,0x6)     => format!("SHR V{:X}, V{:X}", x, y),
+        (0x8,_,_,0x7)     => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x8,_,_,0xe)     => format!("SHL V{:X}, V{:X}", x, y),
+        (0x9,_,_,0x0)     => format!("SNE V{:X}, V{:X}", x, y),
+        (0xa,_,_,_)       => format!("LD I, {}", address(nnn)),
+        (0xb,_,_,_)       => format!("JP V0, {}", address(nnn)),
+        (0xc,_,_,_)       => format!("RND V{:X}, 0x{:02X}", x, kk),
+        (0xd,_,_,_)       => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xe,_,0x9,0xe)   => format!("SKP V{:X}", x),
+        (0xe,_,0xa,0x1)   => format!("SKNP V{:X}", x),
+        (0xf,0x0,0x0,0x0) => format!("LD I, LONG {}", address(next?)),
+        (0xf,_,0x0,0x1)   => format!("PLANE {}", x),
+        (0xf,0x0,0x0,0x2) => "AUDIO".to_string(),
+        (0xf,_,0x0,0x7)   => format!("LD V{:X}, DT", x),
+        (0xf,_,0x0,0xa)   => format!("LD V{:X}, K", x),
+        (0xf,_,0x1,0x5)   => format!("LD DT, V{:X}", x),
+        (0xf,_,0x1,0x8)   => format!("LD ST, V{:X}", x),
+        (0xf,_,0x1,0xe)   => format!("ADD I, V{:X}", x),
+        (0xf,_,0x2,0x9)   => format!("LD F, V{:X}", x),
+        (0xf,_,0x3,0x0)   => format!("LD HF, V{:X}", x),
+        (0xf,_,0x3,0x3)   => format!("LD B, V{:X}", x),
+        (0xf,_,0x3,0xa)   => format!("PITCH V{:X}", x),
+        (0xf,_,0x5,0x5)   => format!("LD [I], V{:X}", x),
+        (0xf,_,0x6,0x5)   => format!("LD V{:X}, [I]", x),
+        (0xf,_,0x7,0x5)   => format!("LD R, V{:X}", x),
+        (0xf,_,0x8,0x5)   => format!("LD V{:X}, R", x),
+        (_,_,_,_)         => return None
+    };
+
+    Some(text)
+}
+
+/// Where the execution can go after an instruction.
+enum Flow {
+    // Next instruction
+    Next,
+    // Next instruction, or the one after it
+    Skip,
+    // Jump to an address, and come back for CALL
+    Jump(u16),
+    Call(u16),
+    // Execution does not continue after this instruction
+    Stop
+}
+
+fn flow(opcode: u16) -> Flow {
+    let nnn = opcode & 0x0FFF;
+
+    match opcode & 0xF000 {
+        0x0000 if opcode == 0x00EE => Flow::Stop,
+        0x0000 if opcode == 0x00FD => Flow::Stop,
+        0x1000                     => Flow::Jump(nnn),
+        0x2000                     => Flow::Call(nnn),
+        0x3000 | 0x4000 | 0xE000   => Flow::Skip,
+        // Unlike 5XY0, the XO-CHIP 5XY2 and 5XY3 do not skip
+        0x5000 | 0x9000 if opcode & 0x000F == 0 => Flow::Skip,
+        // The target of JP V0 depends on V0, only its base is known
+        0xB000                     => Flow::Stop,
+        _                          => Flow::Next
+    }
+}
+
+/// Disassembly of a ROM loaded at 0x200.
+///
+/// Code is told from data by following the control flow from the entry
+/// point: every byte that is never reached as an instruction is data.
+pub struct Disassembly {
+    rom: Vec<u8>,
+    // Addresses where an instruction starts
+    code: BTreeSet<u16>,
+    // Targets of JP, CALL and LD I, with their label
+    labels: BTreeMap<u16, String>
+}
+
+impl Disassembly {
+    /// Run the control flow analysis on the ROM.
+    ///
+    /// # Arguments
+    ///
+    /// * `rom` - A buffer containing the game
+    pub fn new(rom: &[u8]) -> Self {
+        let mut disassembly = Disassembly {
+            rom: rom.to_vec(),
+            code: BTreeSet::new(),
+            labels: BTreeMap::new()
+        };
+
+        disassembly.analyze();
+
+        disassembly
+    }
+
+    fn analyze(&mut self) {
+        let mut pending = vec![PROGRAM_START];
+
+        while let Some(address) = pending.pop() {
+            if self.code.contains(&address) {
+                continue;
+            }
+
+            let opcode = match self.opcode(address) {
+                Some(opcode) => opcode,
+                None => continue
+            };
+
+            let size = instruction_size(opcode);
+            let next = self.opcode(address.wrapping_add(OPCODE_SIZE));
+            if format_instruction(opcode, next, &|nnn| nnn.to_string()).is_none() {
+                continue;
+            }
+
+            self.code.insert(address);
+
+            let following = address.wrapping_add(size);
+            match flow(opcode) {
+                Flow::Next => pending.push(following),
+                Flow::Skip => {
+                    // The skipped instruction may be a long one
+                    let skipped = self.opcode(following).map(instruction_size).unwrap_or(OPCODE_SIZE);
+                    pending.push(following.wrapping_add(skipped));
+                    pending.push(following);
+                },
+                Flow::Jump(target) => {
+                    self.add_label(target);
+                    pending.push(target);
+                },
+                Flow::Call(target) => {
+                    self.add_label(target);
+                    pending.push(following);
+                    pending.push(target);
+                },
+                Flow::Stop => {}
+            }
+
+            // Data referenced by LD I and jump tables get a label too
+            if opcode & 0xF000 == 0xA000 || opcode & 0xF000 == 0xB000 {
+                self.add_label(opcode & 0x0FFF);
+            }
+            if let (0xF000, Some(target)) = (opcode, next) {
+                self.add_label(target);
+            }
+        }
+    }
+
+    fn add_label(&mut self, address: u16) {
+        if self.contains(address) {
+            self.labels.insert(address, format!("L{:03X}", address));
+        }
+    }
+
+    fn contains(&self, address: u16) -> bool {
+        address >= PROGRAM_START && ((address - PROGRAM_START) as usize) < self.rom.len()
+    }
+
+    fn byte(&self, address: u16) -> u8 {
+        self.rom[(address - PROGRAM_START) as usize]
+    }
+
+    fn opcode(&self, address: u16) -> Option<u16> {
+        if !self.contains(address) || !self.contains(address.wrapping_add(1)) {
+            return None;
+        }
+
+        Some(((self.byte(address) as u16) << 8) | self.byte(address.wrapping_add(1)) as u16)
+    }
+
+    /// Whether an instruction starts at this address.
+    pub fn is_code(&self, address: u16) -> bool {
+        self.code.contains(&address)
+    }
+
+    /// Label given to an address, if it is the target of a jump,
+    /// a call or an `LD I`.
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    /// Print the ROM as address, raw bytes and mnemonic.
+    ///
+    /// Data bytes are printed one per line with their bit pattern, which
+    /// makes sprites easy to spot.
+    pub fn listing(&self) -> String {
+        self.print(false)
+    }
+
+    /// Print the ROM as source code, that the assembler turns back
+    /// into the same bytes.
+    pub fn source(&self) -> String {
+        self.print(true)
+    }
+
+    fn print(&self, source: bool) -> String {
+        let mut text = String::new();
+        let mut offset = 0;
+
+        while offset < self.rom.len() {
+            let address = PROGRAM_START.wrapping_add(offset as u16);
+            if let Some(label) = self.label(address) {
+                let _ = writeln!(text, "{}:", label);
+            }
+
+            // An instruction overlapping a label is printed as data so
+            // that the label still points to the start of a line.
+            let instruction = self.opcode(address).filter(|&opcode| {
+                self.is_code(address) && (1 .. instruction_size(opcode))
+                    .all(|i| self.label(address.wrapping_add(i)).is_none())
+            });
+
+            match instruction {
+                Some(opcode) => {
+                    let size = instruction_size(opcode);
+                    let next = self.opcode(address.wrapping_add(OPCODE_SIZE));
+                    let mnemonic = format_instruction(opcode, next, &|nnn| self.address(nnn)).unwrap();
+                    let _ = if source {
+                        writeln!(text, "    {}", mnemonic)
+                    } else {
+                        let bytes: Vec<String> = self.rom[offset .. offset + size as usize].iter()
+                            .map(|byte| format!("{:02X}", byte))
+                            .collect();
+                        writeln!(text, "{:03X}  {:<11}  {}", address, bytes.join(" "), mnemonic)
+                    };
+                    offset += size as usize;
+                },
+                None => {
+                    let byte = self.rom[offset];
+                    let _ = if source {
+                        writeln!(text, "    db 0x{:02X}  ; {}", byte, bitmap(byte))
+                    } else {
+                        writeln!(text, "{:03X}  {:02X}           db 0x{:02X}  ; {}",
+                                 address, byte, byte, bitmap(byte))
+                    };
+                    offset += 1;
+                }
+            }
+        }
+
+        text
+    }
+
+    fn address(&self, nnn: u16) -> String {
+        match self.label(nnn) {
+            Some(label) => label.to_string(),
+            None => format!("0x{:03X}", nnn)
+        }
+    }
+}
+
+/// Bit pattern of a sprite row, e.g. `#..##...`.
+fn bitmap(byte: u8) -> String {
+    (0 .. 8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD I, L208 / CALL L206 / JP L204 / RET, then a sprite
+    const ROM: [u8; 10] = [0xa2, 0x08, 0x22, 0x06, 0x12, 0x04, 0x00, 0xee, 0x3c, 0x42];
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(mnemonic(0x631f).as_deref(), Some("LD V3, 0x1F"));
+        assert_eq!(mnemonic(0xd125).as_deref(), Some("DRW V1, V2, 5"));
+        assert_eq!(mnemonic(0x2abc).as_deref(), Some("CALL 0xABC"));
+        assert_eq!(mnemonic(0x5122).as_deref(), Some("SAVE V1, V2"));
+        assert_eq!(mnemonic(0x5121), None);
+        assert_eq!(mnemonic(0xf000), None);
+        assert_eq!(mnemonic_at(&[0xf0, 0x00, 0x12, 0x34], 0).as_deref(), Some("LD I, LONG 0x1234"));
+    }
+
+    #[test]
+    fn code_is_told_from_data() {
+        let disassembly = Disassembly::new(&ROM);
+
+        let code: Vec<bool> = (0x200 .. 0x20a).step_by(2).map(|address| disassembly.is_code(address)).collect();
+        assert_eq!(code, vec![true, true, true, true, false]);
+    }
+
+    #[test]
+    fn labels_mark_jump_call_and_load_targets() {
+        let disassembly = Disassembly::new(&ROM);
+
+        let labels: Vec<_> = (0x200 .. 0x20a).filter_map(|address| disassembly.label(address)).collect();
+        assert_eq!(labels, vec!["L204", "L206", "L208"]);
+    }
+
+    #[test]
+    fn listing_and_source() {
+        let disassembly = Disassembly::new(&ROM);
+
+        assert_eq!(disassembly.listing(), "\
+200  A2 08        LD I, L208
+202  22 06        CALL L206
+L204:
+204  12 04        JP L204
+L206:
+206  00 EE        RET
+L208:
+208  3C           db 0x3C  ; ..####..
+209  42           db 0x42  ; .#....#.
+");
+        assert_eq!(disassembly.source(), "    LD I, L208
+    CALL L206
+L204:
+    JP L204
+L206:
+    RET
+L208:
+    db 0x3C  ; ..####..
+    db 0x42  ; .#....#.
+");
+    }
+
+    #[test]
+    fn xochip_save_and_load_do_not_skip() {
+        // SAVE V1, V2 / JP 202, then an unreachable LD V0, 01
+        let disassembly = Disassembly::new(&[0x51, 0x22, 0x12, 0x02, 0x60, 0x01]);
+
+        assert!(!disassembly.is_code(0x204));
+    }
+}
@@ -0,0 +1,131 @@
+/// How the screen is scaled to fill the window.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScaleMode {
+    /// Largest whole multiple of the screen size that fits, every pixel
+    /// has the same size
+    #[default]
+    Integer,
+    /// As large as the window allows, keeping the aspect ratio
+    Fit
+}
+
+impl ScaleMode {
+    /// Names accepted by `from_name`.
+    pub const NAMES: [&'static str; 2] = ["integer", "fit"];
+
+    pub fn from_name(name: &str) -> Option<ScaleMode> {
+        match name.to_ascii_lowercase().as_str() {
+            "integer" => Some(ScaleMode::Integer),
+            "fit"     => Some(ScaleMode::Fit),
+            _ => None
+        }
+    }
+}
+
+/// Window of the graphics backends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplaySettings {
+    /// Size of a low resolution pixel in the window at start
+    pub scale: u32,
+    pub scale_mode: ScaleMode,
+    pub fullscreen: bool
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            scale: 16,
+            scale_mode: ScaleMode::Integer,
+            fullscreen: false
+        }
+    }
+}
+
+/// Size of the screenshots.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScreenshotScale {
+    /// One pixel per pixel of the display, 64x32 or 128x64
+    Native,
+    /// The size of a pixel in the window
+    #[default]
+    Window,
+    /// Every pixel of the display drawn as a square of this size
+    Times(u32)
+}
+
+impl ScreenshotScale {
+    /// Names accepted by `from_name`, besides a number.
+    pub const NAMES: [&'static str; 2] = ["native", "window"];
+
+    pub fn from_name(name: &str) -> Option<ScreenshotScale> {
+        match name.to_ascii_lowercase().as_str() {
+            "native" => Some(ScreenshotScale::Native),
+            "window" => Some(ScreenshotScale::Window),
+            scale => scale.parse().ok().filter(|&scale| scale > 0).map(ScreenshotScale::Times)
+        }
+    }
+}
+
+/// Area of the window the screen is drawn in, centered with black bars
+/// around it: x, y, width and height.
+///
+/// # Arguments
+///
+/// * `window` - Width and height of the window
+/// * `screen` - Width and height of the screen, 64x32 or 128x64
+/// * `mode` - How the screen is scaled
+pub fn viewport(window: (u32, u32), screen: (u32, u32), mode: ScaleMode) -> (i32, i32, u32, u32) {
+    let (window_width, window_height) = window;
+    let (screen_width, screen_height) = (screen.0.max(1), screen.1.max(1));
+
+    let (width, height) = match mode {
+        ScaleMode::Integer => {
+            // A window smaller than the screen still shows it, cropped
+            let scale = (window_width / screen_width).min(window_height / screen_height).max(1);
+            (screen_width * scale, screen_height * scale)
+        },
+        ScaleMode::Fit => {
+            let scale = (window_width as f32 / screen_width as f32).min(window_height as f32 / screen_height as f32);
+            ((screen_width as f32 * scale) as u32, (screen_height as f32 * scale) as u32)
+        }
+    };
+
+    let x = (window_width as i32 - width as i32) / 2;
+    let y = (window_height as i32 - height as i32) / 2;
+
+    (x, y, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_scaling_letterboxes() {
+        assert_eq!(viewport((1024, 512), (64, 32), ScaleMode::Integer), (0, 0, 1024, 512));
+        // 15 times, with bars on every side
+        assert_eq!(viewport((1000, 600), (64, 32), ScaleMode::Integer), (20, 60, 960, 480));
+        assert_eq!(viewport((1000, 600), (128, 64), ScaleMode::Integer), (52, 76, 896, 448));
+    }
+
+    #[test]
+    fn integer_scaling_crops_a_small_window() {
+        assert_eq!(viewport((40, 20), (64, 32), ScaleMode::Integer), (-12, -6, 64, 32));
+    }
+
+    #[test]
+    fn fit_scaling_keeps_the_aspect_ratio() {
+        assert_eq!(viewport((1000, 600), (64, 32), ScaleMode::Fit), (0, 50, 1000, 500));
+        assert_eq!(viewport((1000, 400), (128, 64), ScaleMode::Fit), (100, 0, 800, 400));
+        assert_eq!(viewport((1024, 512), (64, 32), ScaleMode::Fit), (0, 0, 1024, 512));
+    }
+
+    #[test]
+    fn screenshot_scale_names() {
+        assert_eq!(ScreenshotScale::from_name("Native"), Some(ScreenshotScale::Native));
+        assert_eq!(ScreenshotScale::from_name("window"), Some(ScreenshotScale::Window));
+        assert_eq!(ScreenshotScale::from_name("3"), Some(ScreenshotScale::Times(3)));
+        assert_eq!(ScreenshotScale::from_name("0"), None);
+        assert_eq!(ScreenshotScale::from_name("big"), None);
+    }
+}
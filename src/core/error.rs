@@ -0,0 +1,124 @@
+use std::fmt;
+use std::fmt::Write;
+use std::io;
+
+use crate::core::mnemonic_at;
+use crate::core::Processor;
+
+/// Error raised by the virtual machine.
+///
+/// Faults of the running program carry the address and the opcode of the
+/// faulty instruction.
+#[derive(Debug)]
+pub enum Chip8Error {
+    /// The opcode is not part of the instruction set
+    InvalidOpcode { pc: u16, opcode: u16 },
+    /// CALL with the 16 levels of the stack in use
+    StackOverflow { pc: u16, opcode: u16 },
+    /// RET outside of a subroutine
+    StackUnderflow { pc: u16, opcode: u16 },
+    /// Access past the end of memory, at `address`
+    MemoryOutOfBounds { pc: u16, opcode: u16, address: usize },
+    /// The ROM does not fit in the program area
+    RomTooLarge { size: usize, max: usize },
+    Io(io::Error)
+}
+
+impl Chip8Error {
+    /// Address of the faulty instruction, if the error comes from the program.
+    pub fn pc(&self) -> Option<u16> {
+        match *self {
+            Chip8Error::InvalidOpcode { pc, .. } |
+            Chip8Error::StackOverflow { pc, .. } |
+            Chip8Error::StackUnderflow { pc, .. } |
+            Chip8Error::MemoryOutOfBounds { pc, .. } => Some(pc),
+            Chip8Error::RomTooLarge { .. } |
+            Chip8Error::Io(_) => None
+        }
+    }
+
+    /// Faulty instruction, if the error comes from the program.
+    pub fn opcode(&self) -> Option<u16> {
+        match *self {
+            Chip8Error::InvalidOpcode { opcode, .. } |
+            Chip8Error::StackOverflow { opcode, .. } |
+            Chip8Error::StackUnderflow { opcode, .. } |
+            Chip8Error::MemoryOutOfBounds { opcode, .. } => Some(opcode),
+            Chip8Error::RomTooLarge { .. } |
+            Chip8Error::Io(_) => None
+        }
+    }
+
+    /// Describe the error along with the state of the machine: registers,
+    /// stack and the instructions around the fault.
+    pub fn report(&self, processor: &Processor) -> String {
+        let mut text = String::new();
+
+        let _ = writeln!(text, "Fault: {}", self);
+        let _ = writeln!(text);
+
+        for x in 0 .. 16 {
+            let _ = write!(text, "V{:X}={:02x}{}", x, processor.v(x), if x % 8 == 7 { "\n" } else { "  " });
+        }
+        let _ = writeln!(text, "I={:#05x}  PC={:#05x}  SP={}  DT={}  ST={}",
+                         processor.i(), processor.pc(), processor.sp(),
+                         processor.delay_timer(), processor.sound_timer());
+
+        let stack: Vec<String> = processor.stack().iter().rev().map(|addr| format!("{:#05x}", addr)).collect();
+        let _ = writeln!(text, "Stack: {}", if stack.is_empty() { "empty".to_string() } else { stack.join(" ") });
+
+        if let Some(pc) = self.pc() {
+            let _ = writeln!(text);
+            let start = pc.saturating_sub(6);
+            for address in (start ..= pc.saturating_add(6)).step_by(2) {
+                let instruction = match mnemonic_at(processor.memory(), address as usize) {
+                    Some(instruction) => instruction,
+                    None if (address as usize) < processor.memory().len() => "???".to_string(),
+                    None => continue
+                };
+                let marker = if address == pc { "=>" } else { "  " };
+                let _ = writeln!(text, "{} {:03X}  {}", marker, address, instruction);
+            }
+        }
+
+        text.trim_end().to_string()
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::InvalidOpcode { pc, opcode } =>
+                write!(f, "invalid opcode {:04x} at {:#05x}", opcode, pc),
+            Chip8Error::StackOverflow { pc, opcode } =>
+                write!(f, "stack overflow, {:04x} at {:#05x}", opcode, pc),
+            Chip8Error::StackUnderflow { pc, opcode } =>
+                write!(f, "stack underflow, {:04x} at {:#05x}", opcode, pc),
+            Chip8Error::MemoryOutOfBounds { pc, opcode, address } =>
+                write!(f, "memory access out of bounds at {:#05x}, {:04x} at {:#05x}", address, opcode, pc),
+            Chip8Error::RomTooLarge { size, max } =>
+                write!(f, "the ROM is {} bytes, at most {} fit in memory", size, max),
+            Chip8Error::Io(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(e: io::Error) -> Self {
+        Chip8Error::Io(e)
+    }
+}
+
+/// Error of a file that can't be parsed: save states, recordings, configs.
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,288 @@
+use std::collections::VecDeque;
+
+use crate::core::CHIP8_HEIGHT;
+use crate::core::CHIP8_WIDTH;
+use crate::core::Palette;
+use crate::core::SCHIP_HEIGHT;
+use crate::core::SCHIP_WIDTH;
+use crate::core::Vram;
+
+/// Frames averaged by `Filter::Blend` unless told otherwise.
+pub const DEFAULT_BLEND_FRAMES: usize = 3;
+
+/// Share of its brightness an erased pixel keeps every frame with
+/// `Filter::Phosphor` unless told otherwise.
+pub const DEFAULT_PHOSPHOR_DECAY: f32 = 0.6;
+
+/// Display shown to the player, as RGB bytes row by row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>
+}
+
+impl Frame {
+    /// The frame with every pixel drawn as a square of `scale` pixels.
+    pub fn scaled(&self, scale: usize) -> Frame {
+        let scale = scale.max(1);
+        let width = self.width * scale;
+
+        let mut pixels = Vec::with_capacity(self.pixels.len() * scale * scale);
+        for row in self.pixels.chunks(self.width * 3) {
+            let start = pixels.len();
+            for pixel in row.chunks(3) {
+                for _ in 0 .. scale {
+                    pixels.extend_from_slice(pixel);
+                }
+            }
+            for _ in 1 .. scale {
+                pixels.extend_from_within(start .. start + width * 3);
+            }
+        }
+
+        Frame {
+            width,
+            height: self.height * scale,
+            pixels
+        }
+    }
+}
+
+/// How the display is turned into frames.
+///
+/// Games erase a sprite by drawing it again before drawing it elsewhere,
+/// so a sprite that moves is missing from some frames and flickers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Filter {
+    /// The display as it is at the end of each 60 Hz frame
+    #[default]
+    Vblank,
+    /// Average of the last frames, a flickering pixel is shown dimmed
+    Blend(usize),
+    /// Lit pixels show at once and fade away once erased, like on a CRT,
+    /// keeping this share of their brightness every frame
+    Phosphor(f32)
+}
+
+impl Filter {
+    /// Names accepted by `from_name`.
+    pub const NAMES: [&'static str; 3] = ["vblank", "blend", "phosphor"];
+
+    /// A name, optionally followed by the number of frames to blend or the
+    /// decay of the phosphor, e.g. `blend:4` or `phosphor:0.8`.
+    pub fn from_name(name: &str) -> Option<Filter> {
+        let mut parts = name.splitn(2, ':');
+        let name = parts.next()?.to_ascii_lowercase();
+        let parameter = parts.next();
+
+        match (name.as_str(), parameter) {
+            ("vblank", None) => Some(Filter::Vblank),
+            ("blend", None) => Some(Filter::Blend(DEFAULT_BLEND_FRAMES)),
+            ("blend", Some(frames)) => frames.parse().ok().filter(|&frames| frames > 0).map(Filter::Blend),
+            ("phosphor", None) => Some(Filter::Phosphor(DEFAULT_PHOSPHOR_DECAY)),
+            ("phosphor", Some(decay)) => {
+                decay.parse().ok().filter(|decay| (0.0 .. 1.0).contains(decay)).map(Filter::Phosphor)
+            },
+            _ => None
+        }
+    }
+}
+
+/// Turns the display into frames, keeping what the filter needs of the
+/// previous ones.
+pub struct FrameFilter {
+    filter: Filter,
+    // Last frames for Blend, the newest last
+    history: VecDeque<Vec<u8>>,
+    // Brightness of every colour component for Phosphor
+    phosphor: Vec<f32>
+}
+
+impl FrameFilter {
+    pub fn new(filter: Filter) -> Self {
+        FrameFilter {
+            filter,
+            history: VecDeque::new(),
+            phosphor: Vec::new()
+        }
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// Whether the frames change while the display does not, so that one
+    /// must be made every 60 Hz frame.
+    pub fn is_animated(&self) -> bool {
+        self.filter != Filter::Vblank
+    }
+
+    /// Forget the previous frames, e.g. after loading a state.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.phosphor.clear();
+    }
+
+    /// Make the next frame, call once per 60 Hz frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `vram` - The display
+    /// * `hires` - Whether the display is in 128x64
+    /// * `palette` - Colours of the pixels
+    pub fn apply(&mut self, vram: &Vram, hires: bool, palette: &Palette) -> Frame {
+        let frame = render(vram, hires, palette);
+
+        // The previous frames can't be mixed with frames of another size
+        let size = frame.pixels.len();
+        if self.history.front().is_some_and(|previous| previous.len() != size) ||
+           (!self.phosphor.is_empty() && self.phosphor.len() != size) {
+            self.reset();
+        }
+
+        match self.filter {
+            Filter::Vblank => frame,
+            Filter::Blend(frames) => {
+                self.history.push_back(frame.pixels.clone());
+                while self.history.len() > frames {
+                    self.history.pop_front();
+                }
+
+                let count = self.history.len() as u32;
+                let pixels = (0 .. size).map(|i| {
+                    let sum: u32 = self.history.iter().map(|pixels| pixels[i] as u32).sum();
+                    ((sum + count / 2) / count) as u8
+                }).collect();
+
+                Frame { pixels, ..frame }
+            },
+            Filter::Phosphor(decay) => {
+                if self.phosphor.is_empty() {
+                    self.phosphor = frame.pixels.iter().map(|&component| component as f32).collect();
+                }
+
+                // Erased pixels fade towards the background, the others
+                // take their colour at once
+                let background = palette.color(0);
+                for (i, brightness) in self.phosphor.iter_mut().enumerate() {
+                    let target = frame.pixels[i] as f32;
+                    let lit = frame.pixels[i - i % 3 .. i - i % 3 + 3] != background;
+                    *brightness = if lit { target } else { target + (*brightness - target) * decay };
+                }
+
+                Frame {
+                    pixels: self.phosphor.iter().map(|&brightness| brightness.round() as u8).collect(),
+                    ..frame
+                }
+            }
+        }
+    }
+}
+
+/// Colours of the display, without any filter.
+pub fn render(vram: &Vram, hires: bool, palette: &Palette) -> Frame {
+    let (width, height) = if hires {
+        (SCHIP_WIDTH, SCHIP_HEIGHT)
+    } else {
+        (CHIP8_WIDTH, CHIP8_HEIGHT)
+    };
+
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for row in vram[.. height].iter() {
+        for &pixel in row[.. width].iter() {
+            pixels.extend_from_slice(&palette.color(pixel));
+        }
+    }
+
+    Frame {
+        width,
+        height,
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> Palette {
+        Palette {
+            name: "test".to_string(),
+            colors: [[0, 0, 0], [200, 200, 200], [0, 0, 200], [200, 0, 0]]
+        }
+    }
+
+    // Red component of the top-left pixel in the frames made from a
+    // display where only that pixel is lit or not, frame after frame
+    fn corner(filter: Filter, lit: &[bool]) -> Vec<u8> {
+        let mut frame_filter = FrameFilter::new(filter);
+        lit.iter().map(|&lit| {
+            let mut vram = [[0; SCHIP_WIDTH]; SCHIP_HEIGHT];
+            vram[0][0] = lit as u8;
+            frame_filter.apply(&vram, false, &palette()).pixels[0]
+        }).collect()
+    }
+
+    #[test]
+    fn render_uses_the_palette() {
+        let mut vram = [[0; SCHIP_WIDTH]; SCHIP_HEIGHT];
+        vram[0][1] = 3;
+        vram[1][0] = 2;
+
+        let frame = render(&vram, false, &palette());
+        assert_eq!((frame.width, frame.height, frame.pixels.len()), (64, 32, 64 * 32 * 3));
+        assert_eq!(&frame.pixels[.. 6], &[0, 0, 0, 200, 0, 0]);
+        assert_eq!(&frame.pixels[64 * 3 .. 64 * 3 + 3], &[0, 0, 200]);
+        assert_eq!(render(&vram, true, &palette()).pixels.len(), 128 * 64 * 3);
+    }
+
+    #[test]
+    fn vblank_shows_the_display() {
+        assert_eq!(corner(Filter::Vblank, &[true, false, true]), vec![200, 0, 200]);
+    }
+
+    #[test]
+    fn blend_averages_the_last_frames() {
+        assert_eq!(corner(Filter::Blend(3), &[true, false, false, false]), vec![200, 100, 67, 0]);
+        // A pixel lit every other frame is shown at half its brightness
+        assert_eq!(corner(Filter::Blend(2), &[true, false, true, false]), vec![200, 100, 100, 100]);
+    }
+
+    #[test]
+    fn phosphor_decays_erased_pixels() {
+        assert_eq!(corner(Filter::Phosphor(0.5), &[true, false, false, false, true]), vec![200, 100, 50, 25, 200]);
+        assert_eq!(corner(Filter::Phosphor(0.0), &[true, false]), vec![200, 0]);
+    }
+
+    #[test]
+    fn resolution_change_resets_the_history() {
+        let mut frame_filter = FrameFilter::new(Filter::Blend(2));
+        let mut vram = [[0; SCHIP_WIDTH]; SCHIP_HEIGHT];
+        vram[0][0] = 1;
+        frame_filter.apply(&vram, false, &palette());
+
+        vram[0][0] = 0;
+        let frame = frame_filter.apply(&vram, true, &palette());
+        assert_eq!((frame.width, frame.pixels[0]), (128, 0));
+    }
+
+    #[test]
+    fn filter_names() {
+        assert_eq!(Filter::from_name("vblank"), Some(Filter::Vblank));
+        assert_eq!(Filter::from_name("Blend"), Some(Filter::Blend(DEFAULT_BLEND_FRAMES)));
+        assert_eq!(Filter::from_name("blend:4"), Some(Filter::Blend(4)));
+        assert_eq!(Filter::from_name("blend:0"), None);
+        assert_eq!(Filter::from_name("phosphor:0.8"), Some(Filter::Phosphor(0.8)));
+        assert_eq!(Filter::from_name("phosphor:1"), None);
+        assert_eq!(Filter::from_name("vblank:2"), None);
+    }
+
+    #[test]
+    fn scaled_frame() {
+        let frame = Frame { width: 2, height: 1, pixels: vec![1, 2, 3, 4, 5, 6] };
+        let scaled = frame.scaled(2);
+        assert_eq!((scaled.width, scaled.height), (4, 2));
+        assert_eq!(scaled.pixels, vec![1, 2, 3, 1, 2, 3, 4, 5, 6, 4, 5, 6, 1, 2, 3, 1, 2, 3, 4, 5, 6, 4, 5, 6]);
+    }
+}
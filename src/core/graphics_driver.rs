@@ -1,63 +1,123 @@
 use sdl2::Sdl;
-use sdl2::pixels::Color;
-use sdl2::render::Canvas;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Canvas, Texture};
 use sdl2::rect::Rect;
-use sdl2::video::Window;
+use sdl2::video::{FullscreenType, Window};
 
+use crate::core::display::viewport;
 use crate::core::CHIP8_HEIGHT;
 use crate::core::CHIP8_WIDTH;
+use crate::core::DisplaySettings;
+use crate::core::Frame;
+use crate::core::SCHIP_HEIGHT;
+use crate::core::SCHIP_WIDTH;
+use crate::core::ScaleMode;
 
-const SCALE_FACTOR: u32 = 16;
+// Colour of the bars around the screen
+const BORDER_COLOR: Color = Color { r: 0, g: 0, b: 0, a: 0xff };
 
-const SCREEN_HEIGHT: u32 = (CHIP8_HEIGHT as u32) * SCALE_FACTOR;
-const SCREEN_WIDTH: u32 = (CHIP8_WIDTH as u32) * SCALE_FACTOR;
+const BYTES_PER_PIXEL: usize = 3;
 
 pub struct GraphicsDriver {
-    canvas: Canvas<Window>
+    canvas: Canvas<Window>,
+    // Holds the whole 128x64 display, only the top-left 64x32 is used in
+    // low resolution. Freed with the canvas.
+    texture: Texture,
+    // Last frame shown, held in the top-left of the texture
+    frame: Frame,
+    scale_mode: ScaleMode
 }
 
 impl GraphicsDriver {
-    pub fn new(sdl_context: &Sdl) -> Self {
-        let video_subsystem = sdl_context.video().unwrap();
+    pub fn new(sdl_context: &Sdl, settings: DisplaySettings) -> Result<Self, String> {
+        let video_subsystem = sdl_context.video()?;
 
-        let window = video_subsystem.window("Chip-8 Emulator", SCREEN_WIDTH, SCREEN_HEIGHT)
+        let scale = settings.scale.max(1);
+        let mut window = video_subsystem.window("Chip-8 Emulator", CHIP8_WIDTH as u32 * scale, CHIP8_HEIGHT as u32 * scale)
             .position_centered()
+            .resizable()
             .opengl()
             .build()
-            .unwrap();
+            .map_err(|e| e.to_string())?;
+        window.set_minimum_size(CHIP8_WIDTH as u32, CHIP8_HEIGHT as u32).map_err(|e| e.to_string())?;
+        if settings.fullscreen {
+            window.set_fullscreen(FullscreenType::Desktop)?;
+        }
 
-        let mut canvas = window.into_canvas().build().unwrap();
+        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        let texture = canvas.create_texture_streaming(PixelFormatEnum::RGB24, SCHIP_WIDTH as u32, SCHIP_HEIGHT as u32)
+            .map_err(|e| e.to_string())?;
 
-        canvas.set_draw_color(Color::RGB(2, 34, 49));
+        canvas.set_draw_color(BORDER_COLOR);
         canvas.clear();
         canvas.present();
 
-        GraphicsDriver {
-            canvas
-        }
+        Ok(GraphicsDriver {
+            canvas,
+            texture,
+            frame: Frame {
+                width: CHIP8_WIDTH,
+                height: CHIP8_HEIGHT,
+                pixels: vec![0; CHIP8_WIDTH * CHIP8_HEIGHT * BYTES_PER_PIXEL]
+            },
+            scale_mode: settings.scale_mode
+        })
     }
 
-    pub fn draw(&mut self, vram: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
-        for (y, row) in vram.iter().enumerate() {
-            for (x, &pixel) in row.iter().enumerate() {
-                let xpos = x * SCALE_FACTOR as usize;
-                let ypos = y * SCALE_FACTOR as usize;
-
-                self.canvas.set_draw_color(self.create_color(pixel == 1));
-                let _ =
-                    self.canvas.fill_rect(
-                        Rect::new(xpos as i32, ypos as i32, SCALE_FACTOR, SCALE_FACTOR));
+    /// Show a frame, 64x32 or 128x64.
+    pub fn draw(&mut self, frame: &Frame) {
+        // Upload the pixels in one go, the texture is scaled when copied
+        // to the window
+        let screen = Rect::new(0, 0, frame.width as u32, frame.height as u32);
+        let _ = self.texture.with_lock(screen, |buffer, pitch| {
+            let row_size = frame.width * BYTES_PER_PIXEL;
+            for (y, row) in frame.pixels.chunks(row_size).enumerate() {
+                buffer[y * pitch .. y * pitch + row_size].copy_from_slice(row);
             }
-        }
+        });
+
+        self.frame = frame.clone();
+        self.refresh();
+    }
+
+    /// Frame shown in the window.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
 
+    /// Size of a pixel of the frame in the window, rounded down.
+    pub fn scale(&self) -> u32 {
+        let (screen, window) = self.viewport();
+        (window.width() / screen.width()).max(1)
+    }
+
+    /// Show the last frame again, e.g. once the window is resized.
+    pub fn refresh(&mut self) {
+        let (screen, window) = self.viewport();
+
+        self.canvas.set_draw_color(BORDER_COLOR);
+        self.canvas.clear();
+        let _ = self.canvas.copy(&self.texture, screen, window);
         self.canvas.present();
     }
 
-    fn create_color(&self, is_set: bool) -> Color {
-        if is_set {
-            Color::RGB(162, 191, 221)
-        } else {
-            Color::RGB(2, 34, 49)
-        }
+    /// Switch between the window and fullscreen, returns whether the
+    /// display is now fullscreen.
+    pub fn toggle_fullscreen(&mut self) -> Result<bool, String> {
+        let window = self.canvas.window_mut();
+        let fullscreen = window.fullscreen_state() == FullscreenType::Off;
+        window.set_fullscreen(if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off })?;
+
+        Ok(fullscreen)
+    }
+
+    // Part of the texture holding the frame, and where it is drawn in the
+    // window
+    fn viewport(&self) -> (Rect, Rect) {
+        let screen = (self.frame.width as u32, self.frame.height as u32);
+        let window = self.canvas.output_size().unwrap_or(screen);
+        let (x, y, w, h) = viewport(window, screen, self.scale_mode);
+
+        (Rect::new(0, 0, screen.0, screen.1), Rect::new(x, y, w, h))
     }
 }
@@ -1,8 +1,16 @@
-use sdl2;
-use sdl2::event::Event;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 
-// Below you’ll find an example of the original keypad layout.
+use std::collections::HashMap;
+
+use crate::core::ControllerInput;
+use crate::core::Hotkey;
+use crate::core::KeyEvent;
+use crate::core::Keymap;
+
+// The keyboard is mapped to the keypad by a `Keymap`, by default the 4x4
+// block at the left of a QWERTY keyboard:
 
 // Keypad                   Keyboard
 // +-+-+-+-+                +-+-+-+-+
@@ -15,21 +23,144 @@ use sdl2::keyboard::Keycode;
 // |A|0|B|F|                |Z|X|C|V|
 // +-+-+-+-+                +-+-+-+-+
 
+// How far a stick must be pushed to press the key of its direction
+const AXIS_THRESHOLD: i16 = 16384;
+
+/// Game controller connected or disconnected, with its name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControllerEvent {
+    Connected(String),
+    Disconnected(String)
+}
+
 pub struct InputDriver {
-    event_pump: sdl2::EventPump
+    event_pump: sdl2::EventPump,
+    keys: HashMap<Keycode, u8>,
+    hotkey_bindings: HashMap<Keycode, Hotkey>,
+    hotkeys: Vec<Hotkey>,
+    rewinding: bool,
+    controller_subsystem: sdl2::GameControllerSubsystem,
+    // Opened as they are plugged in
+    controllers: Vec<GameController>,
+    controller_events: Vec<ControllerEvent>,
+    buttons: Vec<(Button, u8)>,
+    // Axis, whether the direction is positive, and its key
+    axes: Vec<(Axis, bool, u8)>,
+    // Keypad returned by the last poll, and the keys it changed
+    keypad: [bool; 16],
+    key_events: Vec<KeyEvent>,
+    // Set when the window was resized or uncovered
+    redraw: bool
 }
 
 impl InputDriver {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
-        InputDriver {
-            event_pump: sdl_context.event_pump().unwrap()
+    pub fn new(sdl_context: &sdl2::Sdl, keymap: &Keymap) -> Result<Self, String> {
+        let keycode = |name: &str| Keycode::from_name(name).ok_or(format!("unknown key `{}`", name));
+
+        let mut keys = HashMap::new();
+        for (name, key) in keymap.keys() {
+            keys.insert(keycode(name)?, key);
+        }
+        let mut hotkey_bindings = HashMap::new();
+        for (name, hotkey) in keymap.hotkeys() {
+            hotkey_bindings.insert(keycode(name)?, hotkey);
+        }
+
+        let mut buttons = Vec::new();
+        let mut axes = Vec::new();
+        for (input, key) in keymap.buttons() {
+            match input {
+                ControllerInput::Button(name) => match Button::from_string(name) {
+                    Some(button) => buttons.push((button, key)),
+                    None => return Err(format!("unknown controller button `{}`", name))
+                },
+                ControllerInput::Axis(name, positive) => match Axis::from_string(name) {
+                    Some(axis) => axes.push((axis, positive, key)),
+                    None => return Err(format!("unknown controller axis `{}`", name))
+                }
+            }
         }
+
+        // Connected controllers are reported as added by the first poll
+        Ok(InputDriver {
+            event_pump: sdl_context.event_pump()?,
+            keys,
+            hotkey_bindings,
+            hotkeys: Vec::new(),
+            rewinding: false,
+            controller_subsystem: sdl_context.game_controller()?,
+            controllers: Vec::new(),
+            controller_events: Vec::new(),
+            buttons,
+            axes,
+            keypad: [false; 16],
+            key_events: Vec::new(),
+            redraw: false
+        })
     }
 
+    /// Hotkeys pressed since the last call.
+    pub fn hotkeys(&mut self) -> Vec<Hotkey> {
+        std::mem::take(&mut self.hotkeys)
+    }
+
+    /// Controllers connected or disconnected since the last call.
+    pub fn controller_events(&mut self) -> Vec<ControllerEvent> {
+        std::mem::take(&mut self.controller_events)
+    }
+
+    /// Keypad keys pressed or released by the last poll.
+    pub fn key_events(&self) -> &[KeyEvent] {
+        &self.key_events
+    }
+
+    /// Whether the window must be drawn again since the last call.
+    pub fn take_redraw(&mut self) -> bool {
+        std::mem::replace(&mut self.redraw, false)
+    }
+
+    /// Whether the rewind key was held at the last poll.
+    pub fn is_rewinding(&self) -> bool {
+        self.rewinding
+    }
+
+    /// State of the keypad.
+    ///
+    /// A key pressed and released between two polls is reported down by the
+    /// first one and up by the next, so that the game sees short taps.
+    #[allow(clippy::result_unit_err)]
     pub fn poll(&mut self) -> Result<[bool; 16], ()> {
-        for event in self.event_pump.poll_iter() {
-            if let Event::Quit {..} = event {
-                return Err(());
+        let mut chip8_keys = [false; 16];
+
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in events {
+            match event {
+                Event::Quit {..} => return Err(()),
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                    // The rewind key is held, see is_rewinding
+                    match self.hotkey_bindings.get(&key) {
+                        Some(Hotkey::Rewind) | None => (),
+                        Some(&hotkey) => self.hotkeys.push(hotkey)
+                    }
+                    if let Some(&i) = self.keys.get(&key) {
+                        chip8_keys[i as usize] = true;
+                    }
+                },
+                Event::ControllerButtonDown { button, .. } => {
+                    for &(bound, key) in self.buttons.iter() {
+                        chip8_keys[key as usize] |= bound == button;
+                    }
+                },
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } |
+                Event::Window { win_event: WindowEvent::Exposed, .. } => self.redraw = true,
+                Event::ControllerDeviceAdded { which, .. } => self.open_controller(which),
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(index) = self.controllers.iter().position(|controller| controller.instance_id() == which) {
+                        let controller = self.controllers.remove(index);
+                        self.controller_events.push(ControllerEvent::Disconnected(controller.name()));
+                    }
+                },
+                _ => {}
             }
         }
 
@@ -39,34 +170,41 @@ impl InputDriver {
                            .filter_map(Keycode::from_scancode)
                            .collect();
 
-        let mut chip8_keys = [false; 16];
+        self.rewinding = keys.iter().any(|key| self.hotkey_bindings.get(key) == Some(&Hotkey::Rewind));
 
         for key in keys {
-            let index = match key {
-                Keycode::Num1 => Some(0x01),
-                Keycode::Num2 => Some(0x02),
-                Keycode::Num3 => Some(0x03),
-                Keycode::Num4 => Some(0x0c),
-                Keycode::Q    => Some(0x04),
-                Keycode::W    => Some(0x05),
-                Keycode::E    => Some(0x06),
-                Keycode::R    => Some(0x0d),
-                Keycode::A    => Some(0x07),
-                Keycode::S    => Some(0x08),
-                Keycode::D    => Some(0x09),
-                Keycode::F    => Some(0x0e),
-                Keycode::Z    => Some(0x0a),
-                Keycode::X    => Some(0x00),
-                Keycode::C    => Some(0x0b),
-                Keycode::V    => Some(0x0f),
-                _             => None
-            };
-
-            if let Some(i) = index {
-                chip8_keys[i] = true;
+            if let Some(&i) = self.keys.get(&key) {
+                chip8_keys[i as usize] = true;
             }
         }
 
-        return Ok(chip8_keys);
+        for controller in self.controllers.iter() {
+            for &(button, key) in self.buttons.iter() {
+                chip8_keys[key as usize] |= controller.button(button);
+            }
+            for &(axis, positive, key) in self.axes.iter() {
+                let value = controller.axis(axis);
+                chip8_keys[key as usize] |= if positive { value > AXIS_THRESHOLD } else { value < -AXIS_THRESHOLD };
+            }
+        }
+
+        self.key_events = KeyEvent::between(&self.keypad, &chip8_keys);
+        self.keypad = chip8_keys;
+
+        Ok(chip8_keys)
+    }
+
+    fn open_controller(&mut self, index: u32) {
+        if !self.controller_subsystem.is_game_controller(index) {
+            return;
+        }
+
+        // A controller may be reported again, keep the opened one
+        if let Ok(controller) = self.controller_subsystem.open(index) {
+            if self.controllers.iter().all(|opened| opened.instance_id() != controller.instance_id()) {
+                self.controller_events.push(ControllerEvent::Connected(controller.name()));
+                self.controllers.push(controller);
+            }
+        }
     }
 }
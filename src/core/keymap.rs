@@ -0,0 +1,431 @@
+use std::io;
+
+use crate::core::error::invalid_data;
+
+/// Emulator commands triggered from the keyboard.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hotkey {
+    SaveState(u8),
+    LoadState(u8),
+    Mute,
+    Trace,
+    /// Held rather than pressed, the game rewinds while the key is down
+    Rewind,
+    Pause,
+    Reset,
+    Fullscreen,
+    Palette,
+    Screenshot,
+    Capture,
+    Quit
+}
+
+impl Hotkey {
+    /// Names used in the key-binding config.
+    pub const NAMES: [&'static str; 18] = [
+        "save1", "save2", "save3", "save4", "load1", "load2", "load3", "load4", "mute", "trace",
+        "rewind", "pause", "reset", "fullscreen", "palette", "screenshot", "capture", "quit"
+    ];
+
+    pub fn from_name(name: &str) -> Option<Hotkey> {
+        match name.to_ascii_lowercase().as_str() {
+            "save1"      => Some(Hotkey::SaveState(1)),
+            "save2"      => Some(Hotkey::SaveState(2)),
+            "save3"      => Some(Hotkey::SaveState(3)),
+            "save4"      => Some(Hotkey::SaveState(4)),
+            "load1"      => Some(Hotkey::LoadState(1)),
+            "load2"      => Some(Hotkey::LoadState(2)),
+            "load3"      => Some(Hotkey::LoadState(3)),
+            "load4"      => Some(Hotkey::LoadState(4)),
+            "mute"       => Some(Hotkey::Mute),
+            "trace"      => Some(Hotkey::Trace),
+            "rewind"     => Some(Hotkey::Rewind),
+            "pause"      => Some(Hotkey::Pause),
+            "reset"      => Some(Hotkey::Reset),
+            "fullscreen" => Some(Hotkey::Fullscreen),
+            "palette"    => Some(Hotkey::Palette),
+            "screenshot" => Some(Hotkey::Screenshot),
+            "capture"    => Some(Hotkey::Capture),
+            "quit"       => Some(Hotkey::Quit),
+            _ => None
+        }
+    }
+
+    /// Name of the hotkey in the key-binding config.
+    pub fn name(self) -> &'static str {
+        Hotkey::NAMES.iter().find(|&&name| Hotkey::from_name(name) == Some(self)).copied().unwrap_or("")
+    }
+}
+
+// Keyboard layouts, the keys of the 4x4 grid row by row
+//
+// Keypad       QWERTY       AZERTY       QWERTZ       Dvorak
+// 1 2 3 C      1 2 3 4      1 2 3 4      1 2 3 4      1 2 3 4
+// 4 5 6 D      Q W E R      A Z E R      Q W E R      ' , . P
+// 7 8 9 E      A S D F      Q S D F      A S D F      A O E U
+// A 0 B F      Z X C V      W X C V      Y X C V      ; Q J K
+
+const KEYPAD: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xc,
+    0x4, 0x5, 0x6, 0xd,
+    0x7, 0x8, 0x9, 0xe,
+    0xa, 0x0, 0xb, 0xf
+];
+
+const QWERTY: [&str; 16] = ["1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V"];
+const AZERTY: [&str; 16] = ["1", "2", "3", "4", "A", "Z", "E", "R", "Q", "S", "D", "F", "W", "X", "C", "V"];
+const QWERTZ: [&str; 16] = ["1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Y", "X", "C", "V"];
+const DVORAK: [&str; 16] = ["1", "2", "3", "4", "'", ",", ".", "P", "A", "O", "E", "U", ";", "Q", "J", "K"];
+
+const DEFAULT_HOTKEYS: [(&str, Hotkey); 18] = [
+    ("F1", Hotkey::SaveState(1)),
+    ("F2", Hotkey::SaveState(2)),
+    ("F3", Hotkey::SaveState(3)),
+    ("F4", Hotkey::SaveState(4)),
+    ("F5", Hotkey::LoadState(1)),
+    ("F6", Hotkey::LoadState(2)),
+    ("F7", Hotkey::LoadState(3)),
+    ("F8", Hotkey::LoadState(4)),
+    ("F9", Hotkey::Mute),
+    ("F10", Hotkey::Trace),
+    ("F11", Hotkey::Fullscreen),
+    ("F12", Hotkey::Reset),
+    ("Pause", Hotkey::Pause),
+    ("Tab", Hotkey::Palette),
+    ("PrintScreen", Hotkey::Screenshot),
+    ("Insert", Hotkey::Capture),
+    ("Backspace", Hotkey::Rewind),
+    ("Escape", Hotkey::Quit)
+];
+
+// Game controllers, the D-pad and the left stick on the arrows of the
+// keypad, 2 4 6 8, the face buttons on 5 0 A B
+const DEFAULT_BUTTONS: [(&str, u8); 12] = [
+    ("dpup", 0x2),
+    ("dpdown", 0x8),
+    ("dpleft", 0x4),
+    ("dpright", 0x6),
+    ("lefty-", 0x2),
+    ("lefty+", 0x8),
+    ("leftx-", 0x4),
+    ("leftx+", 0x6),
+    ("a", 0x5),
+    ("b", 0x0),
+    ("x", 0xa),
+    ("y", 0xb)
+];
+
+// Buttons and axes of the SDL game controller mappings
+const CONTROLLER_BUTTONS: [&str; 15] = [
+    "a", "b", "x", "y", "back", "guide", "start", "leftstick", "rightstick", "leftshoulder",
+    "rightshoulder", "dpup", "dpdown", "dpleft", "dpright"
+];
+const CONTROLLER_AXES: [&str; 6] = ["leftx", "lefty", "rightx", "righty", "lefttrigger", "righttrigger"];
+
+/// Button or stick direction of a game controller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControllerInput {
+    Button(&'static str),
+    /// Axis, and whether the direction is positive
+    Axis(&'static str, bool)
+}
+
+impl ControllerInput {
+    /// Parse a button name, or an axis name followed by `-` or `+`.
+    pub fn from_name(name: &str) -> Option<ControllerInput> {
+        let name = name.to_ascii_lowercase();
+        let find = |names: &[&'static str], name: &str| names.iter().find(|&&known| known == name).copied();
+
+        if let Some(button) = find(&CONTROLLER_BUTTONS, &name) {
+            return Some(ControllerInput::Button(button));
+        }
+
+        let (axis, positive) = match name.strip_suffix('-') {
+            Some(axis) => (axis, false),
+            None => (name.strip_suffix('+')?, true)
+        };
+        find(&CONTROLLER_AXES, axis).map(|axis| ControllerInput::Axis(axis, positive))
+    }
+}
+
+/// Keys of the host keyboard bound to the keypad and to the hotkeys, and
+/// the game controller inputs bound to the keypad.
+///
+/// Keys are named as SDL names them, e.g. `Q`, `Left`, `F1` or `Space`.
+/// Controller buttons use the names of the SDL mappings (`a`, `start`,
+/// `dpup`, `leftshoulder`...), and a direction of a stick is the name of
+/// its axis followed by `-` or `+`, e.g. `leftx-` for left and `lefty-`
+/// for up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    keys: Vec<(String, u8)>,
+    hotkeys: Vec<(String, Hotkey)>,
+    buttons: Vec<(String, u8)>
+}
+
+impl Keymap {
+    /// Names accepted by `layout`.
+    pub const LAYOUTS: [&'static str; 4] = ["qwerty", "azerty", "qwertz", "dvorak"];
+
+    /// Keypad on the 4x4 block at the left of a keyboard layout, with the
+    /// default hotkeys and controller bindings.
+    pub fn layout(name: &str) -> Option<Keymap> {
+        let names = match name.to_ascii_lowercase().as_str() {
+            "qwerty" => QWERTY,
+            "azerty" => AZERTY,
+            "qwertz" => QWERTZ,
+            "dvorak" => DVORAK,
+            _ => return None
+        };
+
+        Some(Keymap {
+            keys: names.iter().zip(KEYPAD.iter()).map(|(name, &key)| (name.to_string(), key)).collect(),
+            hotkeys: DEFAULT_HOTKEYS.iter().map(|&(name, hotkey)| (name.to_string(), hotkey)).collect(),
+            buttons: DEFAULT_BUTTONS.iter().map(|&(name, key)| (name.to_string(), key)).collect()
+        })
+    }
+
+    /// Read a key-binding config.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The parsed TOML config
+    /// * `rom` - File name of the game, whose section in `[roms]` overrides
+    ///   the rest of the config
+    pub fn from_config(config: &toml::Value, rom: Option<&str>) -> Result<Keymap, io::Error> {
+        let mut keymap = Keymap::default();
+        keymap.apply(config)?;
+        if let Some(section) = rom.and_then(|rom| config.get("roms")?.get(rom)) {
+            keymap.apply(section)?;
+        }
+
+        // A key pressing a keypad key would also trigger the hotkey
+        for (name, key) in keymap.keys() {
+            if let Some((_, hotkey)) = keymap.hotkeys().find(|(bound, _)| bound.eq_ignore_ascii_case(name)) {
+                return Err(invalid_data(&format!("`{}` is bound to both keypad key {:X} and hotkey {}",
+                                                 name, key, hotkey.name())));
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    /// Keypad key bound to a host key.
+    pub fn key(&self, name: &str) -> Option<u8> {
+        self.keys.iter().find(|(bound, _)| bound.eq_ignore_ascii_case(name)).map(|&(_, key)| key)
+    }
+
+    /// Hotkey bound to a host key.
+    pub fn hotkey(&self, name: &str) -> Option<Hotkey> {
+        self.hotkeys.iter().find(|(bound, _)| bound.eq_ignore_ascii_case(name)).map(|&(_, hotkey)| hotkey)
+    }
+
+    /// Host keys and the keypad keys they are bound to.
+    pub fn keys(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.keys.iter().map(|(name, key)| (name.as_str(), *key))
+    }
+
+    /// Host keys and the hotkeys they are bound to.
+    pub fn hotkeys(&self) -> impl Iterator<Item = (&str, Hotkey)> {
+        self.hotkeys.iter().map(|(name, hotkey)| (name.as_str(), *hotkey))
+    }
+
+    /// Controller buttons and stick directions, and the keypad keys they
+    /// are bound to.
+    pub fn buttons(&self) -> impl Iterator<Item = (ControllerInput, u8)> + '_ {
+        // The names were checked when they were bound
+        self.buttons.iter().filter_map(|(name, key)| Some((ControllerInput::from_name(name)?, *key)))
+    }
+
+    /// Apply the `layout`, `[keys]`, `[hotkeys]` and `[buttons]` of a
+    /// config section.
+    fn apply(&mut self, section: &toml::Value) -> Result<(), io::Error> {
+        if let Some(layout) = section.get("layout") {
+            let name = layout.as_str().unwrap_or("");
+            match Keymap::layout(name) {
+                Some(keymap) => self.keys = keymap.keys,
+                None => return Err(invalid_data(&format!("unknown layout `{}`, expected one of {}",
+                                                         name, Keymap::LAYOUTS.join(", "))))
+            }
+        }
+
+        for (key, value) in table(section, "keys")? {
+            bind(&mut self.keys, keypad_key(key)?, names(value)?);
+        }
+
+        for (hotkey, value) in table(section, "hotkeys")? {
+            let hotkey = match Hotkey::from_name(hotkey) {
+                Some(hotkey) => hotkey,
+                None => return Err(invalid_data(&format!("unknown hotkey `{}`, expected one of {}",
+                                                         hotkey, Hotkey::NAMES.join(", "))))
+            };
+            bind(&mut self.hotkeys, hotkey, names(value)?);
+        }
+
+        for (key, value) in table(section, "buttons")? {
+            let names = names(value)?;
+            if let Some(name) = names.iter().find(|name| ControllerInput::from_name(name).is_none()) {
+                return Err(invalid_data(&format!("unknown controller button `{}`", name)));
+            }
+            bind(&mut self.buttons, keypad_key(key)?, names);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::layout("qwerty").unwrap()
+    }
+}
+
+/// Bind inputs to a key instead of the inputs it was bound to, and remove
+/// their previous bindings.
+fn bind<T: Copy + PartialEq>(bindings: &mut Vec<(String, T)>, key: T, names: Vec<&str>) {
+    bindings.retain(|(_, bound)| *bound != key);
+    for name in names {
+        bindings.retain(|(bound, _)| !bound.eq_ignore_ascii_case(name));
+        bindings.push((name.to_string(), key));
+    }
+}
+
+fn keypad_key(name: &str) -> Result<u8, io::Error> {
+    match u8::from_str_radix(name, 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => Err(invalid_data(&format!("unknown keypad key `{}`, expected 0 to F", name)))
+    }
+}
+
+/// Entries of a table of the config, none if it is missing.
+fn table<'a>(section: &'a toml::Value, name: &str) -> Result<Vec<(&'a str, &'a toml::Value)>, io::Error> {
+    match section.get(name) {
+        Some(toml::Value::Table(table)) => Ok(table.iter().map(|(key, value)| (key.as_str(), value)).collect()),
+        Some(_) => Err(invalid_data(&format!("`{}` must be a table", name))),
+        None => Ok(Vec::new())
+    }
+}
+
+/// Host keys of a binding, a name or a list of names.
+fn names(value: &toml::Value) -> Result<Vec<&str>, io::Error> {
+    let names = match value {
+        toml::Value::String(name) => Some(vec![name.as_str()]),
+        toml::Value::Array(names) => names.iter().map(toml::Value::as_str).collect(),
+        _ => None
+    };
+
+    names.ok_or_else(|| invalid_data("a binding must be a key name or a list of key names"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(text: &str) -> toml::Value {
+        toml::from_str(text).unwrap()
+    }
+
+    fn error(text: &str) -> String {
+        Keymap::from_config(&config(text), None).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn layouts() {
+        let keys = |layout: &str, names: [&str; 2]| {
+            let keymap = Keymap::layout(layout).unwrap();
+            (keymap.key(names[0]), keymap.key(names[1]))
+        };
+
+        assert_eq!(keys("qwerty", ["Q", "Z"]), (Some(0x4), Some(0xa)));
+        assert_eq!(keys("azerty", ["A", "W"]), (Some(0x4), Some(0xa)));
+        assert_eq!(keys("qwertz", ["Q", "Y"]), (Some(0x4), Some(0xa)));
+        assert_eq!(keys("Dvorak", ["'", ";"]), (Some(0x4), Some(0xa)));
+        assert!(Keymap::layout("colemak").is_none());
+
+        let keymap = Keymap::default();
+        assert_eq!(keymap, Keymap::layout("qwerty").unwrap());
+        assert_eq!(keymap.key("v"), Some(0xf));
+        assert_eq!(keymap.hotkey("escape"), Some(Hotkey::Quit));
+    }
+
+    #[test]
+    fn rom_section_overrides_the_config() {
+        let config = config(r#"
+            layout = "azerty"
+
+            [keys]
+            5 = "Space"
+
+            [roms."PONG".keys]
+            1 = ["Up", "W"]
+        "#);
+
+        let pong = Keymap::from_config(&config, Some("PONG")).unwrap();
+        assert_eq!((pong.key("Up"), pong.key("W"), pong.key("1")), (Some(0x1), Some(0x1), None));
+        assert_eq!((pong.key("Space"), pong.key("Z")), (Some(0x5), None));
+        assert_eq!(pong.key("A"), Some(0x4));
+
+        let other = Keymap::from_config(&config, Some("TETRIS")).unwrap();
+        assert_eq!((other.key("1"), other.key("W"), other.key("Up")), (Some(0x1), Some(0xa), None));
+        assert_eq!(other, Keymap::from_config(&config, None).unwrap());
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert_eq!(error("layout = \"colemak\""), "unknown layout `colemak`, expected one of qwerty, azerty, qwertz, dvorak");
+        assert_eq!(error("[keys]\nG = \"Q\""), "unknown keypad key `G`, expected 0 to F");
+        assert_eq!(error("[buttons]\n10 = \"a\""), "unknown keypad key `10`, expected 0 to F");
+        assert!(error("[hotkeys]\nwarp = \"F1\"").starts_with("unknown hotkey `warp`, expected one of save1"));
+        assert_eq!(error("[buttons]\n5 = [\"a\", \"triangle\"]"), "unknown controller button `triangle`");
+        assert_eq!(error("[keys]\n5 = 3"), "a binding must be a key name or a list of key names");
+        assert_eq!(error("keys = 5"), "`keys` must be a table");
+    }
+
+    #[test]
+    fn hotkey_names() {
+        for name in Hotkey::NAMES.iter() {
+            assert_eq!(Hotkey::from_name(name).map(Hotkey::name), Some(*name));
+        }
+        assert_eq!(Hotkey::from_name("Save3"), Some(Hotkey::SaveState(3)));
+        assert_eq!(Hotkey::from_name("load4"), Some(Hotkey::LoadState(4)));
+        assert_eq!(Hotkey::from_name("save5"), None);
+        assert_eq!(Hotkey::LoadState(2).name(), "load2");
+
+        // Every hotkey has a default key
+        for name in Hotkey::NAMES.iter() {
+            let hotkey = Hotkey::from_name(name);
+            assert!(Keymap::default().hotkeys().any(|(_, bound)| Some(bound) == hotkey), "{}", name);
+        }
+    }
+
+    #[test]
+    fn hotkeys_do_not_collide_with_the_keypad() {
+        assert_eq!(error("[hotkeys]\npause = \"Q\""), "`Q` is bound to both keypad key 4 and hotkey pause");
+        assert_eq!(error("[keys]\n0 = \"escape\""), "`escape` is bound to both keypad key 0 and hotkey quit");
+
+        // Moving the hotkey away first is fine
+        let keymap = Keymap::from_config(&config("[keys]\n0 = \"Tab\"\n[hotkeys]\npalette = \"P\""), None).unwrap();
+        assert_eq!((keymap.key("Tab"), keymap.hotkey("P")), (Some(0x0), Some(Hotkey::Palette)));
+
+        // Also after the overrides of a ROM
+        let config = config("[roms.\"PONG\".keys]\n1 = \"F1\"");
+        assert!(Keymap::from_config(&config, None).is_ok());
+        assert!(Keymap::from_config(&config, Some("PONG")).is_err());
+    }
+
+    #[test]
+    fn controller_inputs() {
+        assert_eq!(ControllerInput::from_name("a"), Some(ControllerInput::Button("a")));
+        assert_eq!(ControllerInput::from_name("DPUP"), Some(ControllerInput::Button("dpup")));
+        assert_eq!(ControllerInput::from_name("leftx-"), Some(ControllerInput::Axis("leftx", false)));
+        assert_eq!(ControllerInput::from_name("righttrigger+"), Some(ControllerInput::Axis("righttrigger", true)));
+        assert_eq!(ControllerInput::from_name("leftx"), None);
+        assert_eq!(ControllerInput::from_name("a+"), None);
+        assert_eq!(ControllerInput::from_name("triangle"), None);
+
+        let keymap = Keymap::from_config(&config("[buttons]\n5 = [\"start\", \"rightx+\"]"), None).unwrap();
+        let five: Vec<_> = keymap.buttons().filter(|&(_, key)| key == 0x5).map(|(input, _)| input).collect();
+        assert_eq!(five, vec![ControllerInput::Button("start"), ControllerInput::Axis("rightx", true)]);
+        assert_eq!(keymap.buttons().count(), DEFAULT_BUTTONS.len() + 1);
+    }
+}
@@ -1,15 +1,109 @@
 mod constants;
 mod processor;
+#[cfg(feature = "sdl")]
+mod audio_driver;
+#[cfg(feature = "sdl")]
 mod graphics_driver;
+#[cfg(feature = "sdl")]
 mod input_driver;
+mod assembler;
+mod audio;
+mod capture;
 mod cartridge_driver;
+mod debugger;
+mod disassembler;
+mod display;
+mod error;
+mod filter;
 mod fontset;
+mod keymap;
+mod palette;
+mod png;
+mod quirks;
+mod replay;
+mod rewind;
+mod rng;
+mod scheduler;
+mod snapshot;
+mod trace;
+mod unknown_opcode;
 
 pub use self::constants::CHIP8_HEIGHT;
 pub use self::constants::CHIP8_WIDTH;
+pub use self::constants::SCHIP_HEIGHT;
+pub use self::constants::SCHIP_WIDTH;
 pub use self::fontset::FONTSET;
+pub use self::fontset::FONTSET_LARGE;
+pub use self::snapshot::STATE_VERSION;
 
+pub use self::processor::KeyEvent;
+pub use self::processor::Output;
 pub use self::processor::Processor;
+pub use self::processor::Variant;
+pub use self::processor::Vram;
+pub use self::keymap::ControllerInput;
+pub use self::keymap::Hotkey;
+pub use self::keymap::Keymap;
+pub use self::palette::Palette;
+pub use self::palette::Palettes;
+pub use self::quirks::IndexIncrement;
+pub use self::quirks::Quirks;
+pub use self::replay::InputRecorder;
+pub use self::replay::InputReplay;
+pub use self::replay::ReplayHeader;
+pub use self::replay::rom_hash;
+pub use self::rewind::DEFAULT_REWIND_SECONDS;
+pub use self::rewind::REWIND_INTERVAL;
+pub use self::rewind::Rewind;
+pub use self::rng::Rng;
+pub use self::scheduler::DEFAULT_SPEED;
+pub use self::scheduler::Scheduler;
+pub use self::scheduler::TIMER_FREQUENCY;
+#[cfg(feature = "sdl")]
+pub use self::audio_driver::AudioDriver;
+#[cfg(feature = "sdl")]
 pub use self::graphics_driver::GraphicsDriver;
+#[cfg(feature = "sdl")]
+pub use self::input_driver::ControllerEvent;
+#[cfg(feature = "sdl")]
 pub use self::input_driver::InputDriver;
+pub use self::assembler::AssemblerError;
+pub use self::assembler::assemble;
+pub use self::assembler::assemble_file;
+pub use self::audio::AudioPattern;
+pub use self::audio::AudioSettings;
+pub use self::audio::AudioSink;
+pub use self::audio::NullAudio;
+pub use self::audio::Oscillator;
+pub use self::audio::Waveform;
+pub use self::capture::CaptureFormat;
+pub use self::capture::DEFAULT_CAPTURE_SCALE;
+pub use self::capture::FrameSink;
+pub use self::capture::GifCapture;
+pub use self::capture::Y4mCapture;
 pub use self::cartridge_driver::CartridgeDriver;
+pub use self::debugger::Debugger;
+pub use self::disassembler::Disassembly;
+pub use self::disassembler::mnemonic;
+pub use self::disassembler::mnemonic_at;
+pub use self::display::DisplaySettings;
+pub use self::display::ScaleMode;
+pub use self::display::ScreenshotScale;
+pub use self::display::viewport;
+pub use self::error::Chip8Error;
+pub use self::filter::DEFAULT_BLEND_FRAMES;
+pub use self::filter::DEFAULT_PHOSPHOR_DECAY;
+pub use self::filter::Filter;
+pub use self::filter::Frame;
+pub use self::filter::FrameFilter;
+pub use self::filter::render;
+pub use self::png::encode_png;
+pub use self::trace::BinaryTrace;
+pub use self::trace::CpuState;
+pub use self::trace::RingTrace;
+pub use self::trace::TextTrace;
+pub use self::trace::TraceRecord;
+pub use self::trace::TraceSink;
+pub use self::trace::Tracer;
+pub use self::unknown_opcode::UnknownOpcodePolicy;
+pub use self::unknown_opcode::UnknownOpcodes;
@@ -0,0 +1,234 @@
+use std::io;
+
+use crate::core::error::invalid_data;
+
+/// Colours of the display, indexed by the planes a pixel is set in: none,
+/// the first, the second, both. Only XO-CHIP games draw on the second
+/// plane.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [[u8; 3]; 4]
+}
+
+// Built-in themes: background, first plane, second plane, both planes
+const THEMES: [(&str, [[u8; 3]; 4]); 5] = [
+    ("classic",  [[2, 34, 49], [162, 191, 221], [221, 120, 64], [255, 226, 168]]),
+    ("green",    [[8, 24, 10], [51, 255, 102], [24, 128, 48], [190, 255, 205]]),
+    ("amber",    [[24, 14, 0], [255, 176, 0], [140, 80, 0], [255, 224, 150]]),
+    ("lcd",      [[155, 188, 15], [15, 56, 15], [139, 172, 15], [48, 98, 48]]),
+    ("contrast", [[0, 0, 0], [255, 255, 255], [255, 255, 0], [0, 255, 255]])
+];
+
+impl Palette {
+    /// Names accepted by `theme`.
+    pub const THEMES: [&'static str; 5] = ["classic", "green", "amber", "lcd", "contrast"];
+
+    pub fn theme(name: &str) -> Option<Palette> {
+        let name = name.to_ascii_lowercase();
+        THEMES.iter().find(|(theme, _)| *theme == name).map(|&(theme, colors)| Palette {
+            name: theme.to_string(),
+            colors
+        })
+    }
+
+    /// Colour of a pixel of the display.
+    pub fn color(&self, pixel: u8) -> [u8; 3] {
+        self.colors[(pixel & 0x3) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::theme("classic").unwrap()
+    }
+}
+
+/// The built-in themes and the palettes of the config, one of them in use.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palettes {
+    palettes: Vec<Palette>,
+    selected: usize
+}
+
+impl Palettes {
+    /// The built-in themes, `classic` in use.
+    pub fn new() -> Self {
+        Palettes {
+            palettes: Palette::THEMES.iter().filter_map(|name| Palette::theme(name)).collect(),
+            selected: 0
+        }
+    }
+
+    /// Read the palettes of a config and the one to use.
+    ///
+    /// A palette is a list of two or four colours written `#rrggbb`: the
+    /// background and the foreground, then the colours of the second plane
+    /// and of both planes. With two colours, the second plane is drawn in
+    /// the foreground colour.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The parsed TOML config
+    /// * `rom` - File name of the game, whose section in `[roms]` can pick
+    ///   another palette
+    pub fn from_config(config: &toml::Value, rom: Option<&str>) -> Result<Palettes, io::Error> {
+        let mut palettes = Palettes::new();
+        match config.get("palettes") {
+            Some(toml::Value::Table(table)) => {
+                for (name, colors) in table.iter() {
+                    let palette = Palette {
+                        name: name.to_string(),
+                        colors: parse_colors(name, colors)?
+                    };
+                    // A palette of the config replaces a theme of the same name
+                    palettes.palettes.retain(|other| other.name != palette.name);
+                    palettes.palettes.push(palette);
+                }
+            },
+            Some(_) => return Err(invalid_data("`palettes` must be a table")),
+            None => ()
+        }
+
+        let section = rom.and_then(|rom| config.get("roms")?.get(rom));
+        let selected = section.and_then(|section| section.get("palette")).or_else(|| config.get("palette"));
+        if let Some(name) = selected {
+            let name = name.as_str().unwrap_or("");
+            if !palettes.select(name) {
+                return Err(invalid_data(&format!("unknown palette `{}`, expected one of {}",
+                                                 name, palettes.names().join(", "))));
+            }
+        }
+
+        Ok(palettes)
+    }
+
+    /// Palette in use.
+    pub fn current(&self) -> &Palette {
+        &self.palettes[self.selected]
+    }
+
+    /// Use the palette with this name, returns false if there is none.
+    pub fn select(&mut self, name: &str) -> bool {
+        match self.palettes.iter().position(|palette| palette.name.eq_ignore_ascii_case(name)) {
+            Some(index) => {
+                self.selected = index;
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Use the next palette, after the last one comes the first.
+    pub fn cycle(&mut self) -> &Palette {
+        self.selected = (self.selected + 1) % self.palettes.len();
+        self.current()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.palettes.iter().map(|palette| palette.name.as_str()).collect()
+    }
+}
+
+impl Default for Palettes {
+    fn default() -> Self {
+        Palettes::new()
+    }
+}
+
+fn parse_colors(name: &str, value: &toml::Value) -> Result<[[u8; 3]; 4], io::Error> {
+    let colors: Option<Vec<[u8; 3]>> = value.as_array()
+        .map(|colors| colors.iter().map(|color| color.as_str().and_then(parse_color)).collect())
+        .unwrap_or(None);
+
+    match colors.as_deref() {
+        Some(&[background, foreground]) => Ok([background, foreground, foreground, foreground]),
+        Some(&[background, foreground, second, both]) => Ok([background, foreground, second, both]),
+        _ => Err(invalid_data(&format!("palette `{}` must be a list of 2 or 4 colours written #rrggbb", name)))
+    }
+}
+
+/// Colour written `#rrggbb`.
+fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.strip_prefix('#')?;
+    // from_str_radix would also take a sign
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let component = |i: usize| u8::from_str_radix(&hex[i .. i + 2], 16).ok();
+    Some([component(0)?, component(2)?, component(4)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(text: &str) -> toml::Value {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn colors_are_written_in_hex() {
+        assert_eq!(parse_color("#ff8000"), Some([0xff, 0x80, 0x00]));
+        assert_eq!(parse_color("#FFfFfF"), Some([0xff, 0xff, 0xff]));
+        assert_eq!(parse_color("ff8000"), None);
+        assert_eq!(parse_color("#ff800"), None);
+        assert_eq!(parse_color("#ff80000"), None);
+        assert_eq!(parse_color("#gg8000"), None);
+        assert_eq!(parse_color("#+f+f+f"), None);
+        assert_eq!(parse_color("#ff80é"), None);
+    }
+
+    #[test]
+    fn palettes_have_two_or_four_colours() {
+        let palettes = Palettes::from_config(&config(r##"
+            [palettes]
+            paper = ["#f4f0e6", "#202020"]
+            neon = ["#000000", "#ff00ff", "#00ffff", "#ffffff"]
+        "##), None).unwrap();
+
+        let mut paper = palettes.clone();
+        assert!(paper.select("paper"));
+        // The second plane and both planes use the foreground
+        assert_eq!(paper.current().colors, [[0xf4, 0xf0, 0xe6], [0x20, 0x20, 0x20], [0x20, 0x20, 0x20], [0x20, 0x20, 0x20]]);
+
+        let mut neon = palettes;
+        assert!(neon.select("NEON"));
+        let colors: Vec<_> = (0 .. 4).map(|pixel| neon.current().color(pixel)).collect();
+        assert_eq!(colors, vec![[0x00, 0x00, 0x00], [0xff, 0x00, 0xff], [0x00, 0xff, 0xff], [0xff, 0xff, 0xff]]);
+
+        for colors in ["[\"#000000\"]", "[\"#000000\", \"#ffffff\", \"#ff0000\"]", "[\"#000000\", \"white\"]", "\"#000000\""].iter() {
+            let error = Palettes::from_config(&config(&format!("[palettes]\nbad = {}", colors)), None).unwrap_err();
+            assert_eq!(error.to_string(), "palette `bad` must be a list of 2 or 4 colours written #rrggbb");
+        }
+    }
+
+    #[test]
+    fn unknown_palettes_are_rejected() {
+        let error = Palettes::from_config(&config("palette = \"neon\""), None).unwrap_err();
+        assert_eq!(error.to_string(), "unknown palette `neon`, expected one of classic, green, amber, lcd, contrast");
+
+        let config = config("[roms.\"PONG\"]\npalette = \"neon\"");
+        assert!(Palettes::from_config(&config, None).is_ok());
+        assert!(Palettes::from_config(&config, Some("PONG")).is_err());
+        assert!(Palette::theme("neon").is_none());
+    }
+
+    #[test]
+    fn config_palettes_replace_themes_and_cycle() {
+        let mut palettes = Palettes::from_config(&config(r##"
+            palette = "amber"
+
+            [palettes]
+            green = ["#000000", "#00ff00"]
+        "##), None).unwrap();
+
+        assert_eq!(palettes.current().name, "amber");
+        assert_eq!(palettes.names(), vec!["classic", "amber", "lcd", "contrast", "green"]);
+        assert_eq!(palettes.cycle().name, "lcd");
+        palettes.cycle();
+        palettes.cycle();
+        assert_eq!(palettes.cycle().name, "classic");
+    }
+}
@@ -0,0 +1,230 @@
+use crate::core::Frame;
+
+// PNG layout
+//
+// The signature, then chunks: length (4), type (4), data, CRC-32 of the
+// type and data (4). Big-endian.
+//
+// IHDR  Width, height, bit depth 8, colour type 2 (RGB), no interlace
+// IDAT  Rows, each preceded by its filter type, compressed with zlib
+// IEND  Empty
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+const BYTES_PER_PIXEL: usize = 3;
+
+// Row filters
+const FILTER_SUB: u8 = 1;
+const FILTER_UP:  u8 = 2;
+
+// Deflate matches are 3 to 258 bytes long
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+// First length of each length code from 257, and its number of extra bits
+const LENGTH_BASES: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA_BITS: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+
+const END_OF_BLOCK: u16 = 256;
+
+/// Encode a frame as a PNG image.
+///
+/// The display is made of large areas of the same colour, so filtering
+/// every row against the pixel on its left, or against the row above when
+/// they are the same, then compressing the runs of equal bytes keeps the
+/// images small without a full deflate implementation.
+pub fn encode_png(frame: &Frame) -> Vec<u8> {
+    let row_size = frame.width * BYTES_PER_PIXEL;
+
+    let mut rows = Vec::with_capacity((row_size + 1) * frame.height);
+    let mut previous: Option<&[u8]> = None;
+    for row in frame.pixels.chunks(row_size).take(frame.height) {
+        if previous == Some(row) {
+            rows.push(FILTER_UP);
+            rows.resize(rows.len() + row_size, 0);
+        } else {
+            rows.push(FILTER_SUB);
+            rows.extend(row.iter().enumerate().map(|(i, &byte)| {
+                if i < BYTES_PER_PIXEL { byte } else { byte.wrapping_sub(row[i - BYTES_PER_PIXEL]) }
+            }));
+        }
+        previous = Some(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(frame.width as u32).to_be_bytes());
+    header.extend_from_slice(&(frame.height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib(&rows));
+    write_chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start ..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// zlib stream of a single deflate block with the fixed Huffman codes,
+/// where runs of equal bytes are matches at a distance of 1.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    // Deflate, 32K window, no preset dictionary, fastest compression
+    let mut bits = BitWriter::new(vec![0x78, 0x01]);
+
+    // Last block, fixed codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut i = 0;
+    while i < data.len() {
+        let byte = data[i];
+        bits.write_symbol(byte as u16);
+        i += 1;
+
+        let mut run = data[i ..].iter().take_while(|&&next| next == byte).count();
+        while run >= MIN_MATCH {
+            let length = run.min(MAX_MATCH);
+            bits.write_length(length);
+            // Distance code 0: the previous byte
+            bits.write_code(0, 5);
+            i += length;
+            run -= length;
+        }
+    }
+    bits.write_symbol(END_OF_BLOCK);
+
+    let mut stream = bits.finish();
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+
+    stream
+}
+
+/// Writes the bits of a deflate stream, least significant first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32
+}
+
+impl BitWriter {
+    fn new(bytes: Vec<u8>) -> Self {
+        BitWriter {
+            bytes,
+            buffer: 0,
+            count: 0
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are written most significant bit first.
+    fn write_code(&mut self, code: u32, length: u32) {
+        let reversed = (0 .. length).fold(0, |reversed, bit| (reversed << 1) | ((code >> bit) & 1));
+        self.write(reversed, length);
+    }
+
+    /// A literal byte, the end of the block or a length, with the fixed
+    /// codes.
+    fn write_symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0 ..= 143   => self.write_code(0x30 + symbol, 8),
+            144 ..= 255 => self.write_code(0x190 + symbol - 144, 9),
+            256 ..= 279 => self.write_code(symbol - 256, 7),
+            _           => self.write_code(0xc0 + symbol - 280, 8)
+        }
+    }
+
+    fn write_length(&mut self, length: usize) {
+        let index = LENGTH_BASES.iter().rposition(|&base| base <= length).unwrap_or(0);
+        self.write_symbol(257 + index as u16);
+        self.write((length - LENGTH_BASES[index]) as u32, LENGTH_EXTRA_BITS[index]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+
+        self.bytes
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0 .. 8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 }
+        })
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn long_runs_are_split_into_matches() {
+        // A literal, then matches of 258, 258 and 83 bytes, and 3 literals
+        let mut data = vec![7; 600];
+        data.extend_from_slice(&[1, 2, 3]);
+
+        assert_eq!(zlib(&data), vec![
+            0x78, 0x01, 0x63, 0x1f, 0x05, 0xa3, 0x80, 0x06, 0x80, 0x91, 0x89, 0x19, 0x00,
+            0x76, 0xce, 0x10, 0x6f
+        ]);
+    }
+
+    #[test]
+    fn tiny_image() {
+        // Two red rows, the second filtered against the first
+        let frame = Frame { width: 2, height: 2, pixels: [255, 0, 0].repeat(4) };
+
+        assert_eq!(encode_png(&frame), vec![
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a,
+            0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02,
+            0x08, 0x02, 0x00, 0x00, 0x00, 0xfd, 0xd4, 0x9a, 0x73,
+            0x00, 0x00, 0x00, 0x10, 0x49, 0x44, 0x41, 0x54, 0x78, 0x01, 0x63, 0xfc, 0xcf, 0x00, 0x02, 0x4c,
+            0x0c, 0x60, 0x00, 0x00, 0x0d, 0x1d, 0x01, 0x03, 0xcb, 0xdb, 0x21, 0xa6,
+            0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82
+        ]);
+    }
+}
@@ -1,37 +1,136 @@
-use rand::Rng;
+use std::io;
 
+use crate::core::AudioPattern;
 use crate::core::CHIP8_HEIGHT;
 use crate::core::CHIP8_WIDTH;
+use crate::core::SCHIP_HEIGHT;
+use crate::core::SCHIP_WIDTH;
 use crate::core::FONTSET;
+use crate::core::FONTSET_LARGE;
+use crate::core::Frame;
+use crate::core::Palette;
+use crate::core::encode_png;
+use crate::core::render;
+use crate::core::error::{invalid_data, Chip8Error};
+use crate::core::quirks::{IndexIncrement, Quirks};
+use crate::core::rng::Rng;
+use crate::core::snapshot::{StateReader, StateWriter};
+use crate::core::unknown_opcode::{UnknownOpcodePolicy, UnknownOpcodes};
 
 // System memory map
 // 0x000-0x1FF - Chip 8 interpreter (contains font set in emu)
-// 0x050-0x0A0 - Used for the built in 4x5 pixel font set (0-F)
+// 0x000-0x050 - Used for the built in 4x5 pixel font set (0-F)
+// 0x050-0x0F0 - Used for the built in 8x10 pixel SUPER-CHIP font set (0-F)
 // 0x200-0xFFF - Program ROM and work RAM
+//
+// XO-CHIP extends the program area up to 0xFFFF.
 
 const MEMORY_SIZE:            usize = 4096;
+const XOCHIP_MEMORY_SIZE:     usize = 65536;
 const STACK_SIZE:             usize = 16;
 const KEYPAD_SIZE:            usize = 16;
 const OPCODE_SIZE:              u16 = 2;
 const NUM_REGISTERS:          usize = 16;
 const FONT_AREA_START:        usize = 0x000;
 const FONT_AREA_END:          usize = 0x050;
+const LARGE_FONT_AREA_START:  usize = 0x050;
+const LARGE_FONT_AREA_END:    usize = 0x0f0;
+const RPL_FLAGS_SIZE:         usize = 16;
+const AUDIO_PATTERN_SIZE:     usize = 16;
+const DEFAULT_PITCH:             u8 = 64;
+const NUM_PLANES:                u8 = 2;
 const PROGRAM_AREA_START:     usize = 0x200;
-const PROGRAM_AREA_END:       usize = 0xfff;
+const PROGRAM_AREA_END:       usize = 0x1000;
 
 const WAITING_FOR_INPUT_BIT:     u8 = 0x01;
 const UPDATE_VRAM_BIT:           u8 = 0x02;
+const WAITING_FOR_RELEASE_BIT:   u8 = 0x04;
+const WAITING_FOR_VBLANK_BIT:    u8 = 0x08;
+const VBLANK_BIT:                u8 = 0x10;
+
+/// Instruction set understood by the processor.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Variant {
+    /// CHIP-8 with the SUPER-CHIP 1.1 extensions
+    #[default]
+    Chip8,
+    /// XO-CHIP: 64 KiB of memory, two bitplanes and programmable audio
+    XoChip
+}
+
+/// Display memory, large enough for the SUPER-CHIP high resolution mode.
+///
+/// In low resolution only the top-left 64x32 pixels are used. Each pixel
+/// holds one bit per plane, so it is 0 or 1 unless XO-CHIP draws on the
+/// second plane.
+pub type Vram = [[u8; SCHIP_WIDTH]; SCHIP_HEIGHT];
 
 pub struct Output {
     pub vram_changed: bool,
+    // The sound timer is running
     pub beep_request: bool,
-    pub vram: [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]
+    // The program executed 00FD - EXIT
+    pub exited: bool,
+    // The display is in 128x64 mode
+    pub hires: bool,
+    // An instruction was executed, not the case while FX0A waits for a
+    // key, while DXYN waits for the vertical blank or once the program
+    // has exited
+    pub executed: bool,
+    pub vram: Vram
+}
+
+impl Output {
+    /// Width of the display in the current mode.
+    pub fn width(&self) -> usize {
+        if self.hires { SCHIP_WIDTH } else { CHIP8_WIDTH }
+    }
+
+    /// Height of the display in the current mode.
+    pub fn height(&self) -> usize {
+        if self.hires { SCHIP_HEIGHT } else { CHIP8_HEIGHT }
+    }
+
+    /// Colours of the display in the current mode.
+    pub fn frame(&self, palette: &Palette) -> Frame {
+        render(&self.vram, self.hires, palette)
+    }
+
+    /// The display as a PNG image, every pixel drawn as a square of `scale`
+    /// pixels.
+    pub fn to_png(&self, palette: &Palette, scale: usize) -> Vec<u8> {
+        encode_png(&self.frame(palette).scaled(scale))
+    }
+}
+
+/// A keypad key going down or up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+    pub key: u8,
+    pub pressed: bool
+}
+
+impl KeyEvent {
+    /// Keys that went down or up from one keypad to the next.
+    pub fn between(previous: &[bool; KEYPAD_SIZE], keypad: &[bool; KEYPAD_SIZE]) -> Vec<KeyEvent> {
+        keypad.iter().zip(previous.iter()).enumerate()
+            .filter(|(_, (pressed, was_pressed))| pressed != was_pressed)
+            .map(|(key, (&pressed, _))| KeyEvent { key: key as u8, pressed })
+            .collect()
+    }
 }
 
 /// The Chip-8 virtual machine is represented here
 pub struct Processor {
+    // Instruction set
+    variant: Variant,
+    // Behaviour of the ambiguous instructions
+    quirks: Quirks,
+    // What to do with unknown opcodes, and where they were met
+    unknown_opcode_policy: UnknownOpcodePolicy,
+    unknown_opcodes: UnknownOpcodes,
     // Memory
-    memory: [u8; MEMORY_SIZE],
+    memory: Vec<u8>,
     // Stack
     stack: [u16; STACK_SIZE],
     // Stack pointer
@@ -39,7 +138,14 @@ pub struct Processor {
     // Keypad
     keypad: [bool; KEYPAD_SIZE],
     // Graphics
-    vram: [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
+    vram: Vram,
+    // High resolution mode
+    hires: bool,
+    // XO-CHIP planes affected by drawing, one bit per plane
+    planes: u8,
+    // XO-CHIP audio pattern buffer and pitch
+    audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pitch: u8,
     // CPU registers
     v: [u8; NUM_REGISTERS],
     // Index register
@@ -53,24 +159,51 @@ pub struct Processor {
     // Flags
     cpu_flags: u8,
     // Selected register
-    selected_v: u8
+    selected_v: u8,
+    // SUPER-CHIP RPL user flags
+    rpl: [u8; RPL_FLAGS_SIZE],
+    // Set once the program has executed 00FD - EXIT
+    exited: bool,
+    // Generator of CXKK, and the seed it started from
+    rng: Rng,
+    seed: u64,
+    // Addresses written by the last instruction
+    memory_writes: Vec<u16>
+}
+
+impl Default for Processor {
+    fn default() -> Self {
+        Self::new(Variant::default(), Quirks::default())
+    }
 }
 
 impl Processor {
     /// Initializes the virtual machine
-    pub fn new() -> Self {
-        let mut memory: [u8; MEMORY_SIZE] = [0xff; MEMORY_SIZE];
-        memory[FONT_AREA_START..FONT_AREA_END].copy_from_slice(&FONTSET);
+    ///
+    /// # Arguments
+    ///
+    /// * `variant` - The instruction set of the games that will be loaded
+    /// * `quirks` - The behaviour of the instructions that differ between interpreters
+    pub fn new(variant: Variant, quirks: Quirks) -> Self {
+        let seed = rand::random();
 
         Processor {
+            variant,
+            quirks,
+            unknown_opcode_policy: UnknownOpcodePolicy::default(),
+            unknown_opcodes: UnknownOpcodes::new(),
             // Clear memory
-            memory,
+            memory: Self::initial_memory(variant),
             // Clear stack
             stack: [0xff; STACK_SIZE],
             sp: 0,
             keypad: [false; KEYPAD_SIZE],
             // Cleary display
-            vram: [[0x00; CHIP8_WIDTH]; CHIP8_HEIGHT],
+            vram: [[0x00; SCHIP_WIDTH]; SCHIP_HEIGHT],
+            hires: false,
+            planes: 0x01,
+            audio_pattern: [0x00; AUDIO_PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
             // Clear registers
             v: [0x00; NUM_REGISTERS],
             // Clear index
@@ -82,80 +215,416 @@ impl Processor {
             sound_timer: 0,
             // Clear flags
             cpu_flags: 0,
-            selected_v: 0
+            selected_v: 0,
+            rpl: [0x00; RPL_FLAGS_SIZE],
+            exited: false,
+            // Runs differ unless a seed is given with set_seed
+            rng: Rng::new(seed),
+            seed,
+            memory_writes: Vec::new()
         }
     }
 
+    fn initial_memory(variant: Variant) -> Vec<u8> {
+        let size = match variant {
+            Variant::Chip8  => MEMORY_SIZE,
+            Variant::XoChip => XOCHIP_MEMORY_SIZE
+        };
+
+        let mut memory = vec![0xff; size];
+        memory[FONT_AREA_START..FONT_AREA_END].copy_from_slice(&FONTSET);
+        memory[LARGE_FONT_AREA_START..LARGE_FONT_AREA_END].copy_from_slice(&FONTSET_LARGE);
+
+        memory
+    }
+
     /// Load the game into memory
     ///
     /// # Arguments
     ///
     /// * `game` - A buffer containing the opcodes of the game
-    pub fn load(&mut self, game: &Vec<u8>) {
-        if game.len() > (PROGRAM_AREA_END - PROGRAM_AREA_START) {
-            panic!("Game is too big");
+    pub fn load(&mut self, game: &[u8]) -> Result<(), Chip8Error> {
+        let program_area_end = match self.variant {
+            Variant::Chip8  => PROGRAM_AREA_END,
+            Variant::XoChip => XOCHIP_MEMORY_SIZE
+        };
+
+        let max = program_area_end - PROGRAM_AREA_START;
+        if game.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: game.len(), max });
         }
 
-        for i in 0 .. game.len() {
-            self.memory[PROGRAM_AREA_START + i] = game[i];
+        self.memory[PROGRAM_AREA_START .. PROGRAM_AREA_START + game.len()].copy_from_slice(game);
+
+        Ok(())
+    }
+
+    /// Behaviour of the instructions that differ between interpreters.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Restart the random number generator of CXKK from a seed, making
+    /// the run reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+        self.seed = seed;
+    }
+
+    /// Seed the random number generator started from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Choose what happens when an unknown opcode is executed.
+    pub fn set_unknown_opcode_policy(&mut self, policy: UnknownOpcodePolicy) {
+        self.unknown_opcode_policy = policy;
+    }
+
+    /// Unknown opcodes executed since the machine was created.
+    pub fn unknown_opcodes(&self) -> &UnknownOpcodes {
+        &self.unknown_opcodes
+    }
+
+    /// Current content of the display.
+    pub fn vram(&self) -> &Vram {
+        &self.vram
+    }
+
+    /// Instruction set of the processor.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// XO-CHIP audio pattern, 128 one-bit samples played while the
+    /// sound timer is running.
+    pub fn audio_pattern(&self) -> &[u8] {
+        &self.audio_pattern
+    }
+
+    /// XO-CHIP playback rate of the audio pattern, in Hz.
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// Sound to play instead of the tone while the sound timer is running:
+    /// the audio pattern on XO-CHIP once a game has loaded one with F002.
+    pub fn sound_pattern(&self) -> Option<AudioPattern> {
+        if self.variant != Variant::XoChip || self.audio_pattern.iter().all(|&byte| byte == 0) {
+            return None;
         }
+
+        Some(AudioPattern {
+            samples: self.audio_pattern,
+            rate: self.playback_rate()
+        })
     }
 
-    pub fn tick(&mut self, keypad: [bool; KEYPAD_SIZE]) -> Result<Output, ()> {
-        self.keypad = keypad;
+    /// Whether the display is in high resolution mode.
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Whether a DXYN is waiting for the next 60 Hz frame, see the display
+    /// wait quirk. Nothing is executed until `tick_timers` is called.
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        (self.cpu_flags & WAITING_FOR_VBLANK_BIT) == WAITING_FOR_VBLANK_BIT
+    }
+
+    /// SUPER-CHIP RPL user flags, saved by FX75.
+    ///
+    /// The front-end is responsible for keeping them between runs.
+    pub fn rpl_flags(&self) -> &[u8] {
+        &self.rpl
+    }
+
+    /// Restore the RPL user flags saved by a previous run.
+    pub fn set_rpl_flags(&mut self, flags: &[u8]) {
+        let len = flags.len().min(RPL_FLAGS_SIZE);
+        self.rpl[.. len].copy_from_slice(&flags[.. len]);
+    }
+
+    /// Whole address space of the machine.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Return addresses of the subroutines currently being executed.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[.. self.sp as usize]
+    }
+
+    /// Value of the register Vx.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `x` is not a register, above 0xf.
+    pub fn v(&self, x: u8) -> u8 {
+        self.v[x as usize]
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Opcode that will be executed by the next tick.
+    pub fn opcode(&self) -> u16 {
+        self.read_opcode()
+    }
+
+    /// Addresses written to memory by the last tick.
+    pub fn memory_writes(&self) -> &[u16] {
+        &self.memory_writes
+    }
+
+    /// Set the register Vx.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `x` is not a register, above 0xf.
+    pub fn set_v(&mut self, x: u8, value: u8) {
+        self.v[x as usize] = value;
+    }
+
+    pub fn set_i(&mut self, value: u16) {
+        self.i = value;
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
 
-        if (self.cpu_flags & UPDATE_VRAM_BIT) == UPDATE_VRAM_BIT {
-            self.cpu_flags = 0;
+    /// Patch a byte of memory, bypassing watchpoints.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `addr` is past the end of memory, 0xfff unless the
+    /// variant is XO-CHIP.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize] = value;
+    }
+
+    /// Capture the whole state of the machine in a versioned binary snapshot.
+    ///
+    /// The keypad is not part of the snapshot, it is refreshed on every tick.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        writer.write_u8(self.variant as u8);
+        writer.write_bytes(&self.memory);
+        for &address in self.stack.iter() {
+            writer.write_u16(address);
+        }
+        writer.write_u16(self.sp);
+        writer.write_bytes(&self.v);
+        writer.write_u16(self.i);
+        writer.write_u16(self.pc);
+        writer.write_u8(self.delay_timer);
+        writer.write_u8(self.sound_timer);
+        for row in self.vram.iter() {
+            writer.write_bytes(row);
         }
+        writer.write_u8(self.cpu_flags);
+        writer.write_u8(self.selected_v);
+        writer.write_u8(self.hires as u8);
+        writer.write_bytes(&self.rpl);
+        writer.write_u8(self.exited as u8);
+        writer.write_u8(self.planes);
+        writer.write_bytes(&self.audio_pattern);
+        writer.write_u8(self.pitch);
+        writer.write_u64(self.seed);
+        writer.write_u64(self.rng.state());
+
+        writer.finish()
+    }
 
-        let mut beep_request = false;
+    /// Restore a snapshot taken with `save_state`.
+    ///
+    /// The machine is left untouched if the snapshot is invalid.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - A buffer containing the snapshot
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), io::Error> {
+        let mut reader = StateReader::new(state)?;
 
-        // If the program is waiting for a key
-        if self.cpu_flags & WAITING_FOR_INPUT_BIT == 1 {
-            for i in 0 .. KEYPAD_SIZE {
-                if self.keypad[i] {
-                    // Clear the flag
-                    self.cpu_flags = 0;
-                    // And store what key has been pressed.
-                    self.v[self.selected_v as usize] = i as u8;
-                }
+        // Versions before 3 only had the CHIP-8 memory
+        let variant = if reader.version() < 3 {
+            Variant::Chip8
+        } else {
+            match reader.read_u8()? {
+                0 => Variant::Chip8,
+                1 => Variant::XoChip,
+                _ => return Err(invalid_data("unknown variant"))
             }
+        };
+        let mut memory = Self::initial_memory(variant);
+        let memory_size = memory.len();
+        memory.copy_from_slice(reader.read_bytes(memory_size)?);
+        let mut stack = [0; STACK_SIZE];
+        for address in stack.iter_mut() {
+            *address = reader.read_u16()?;
+        }
+        let sp = reader.read_u16()?;
+        let mut v = [0; NUM_REGISTERS];
+        v.copy_from_slice(reader.read_bytes(NUM_REGISTERS)?);
+        let i = reader.read_u16()?;
+        let pc = reader.read_u16()?;
+        let delay_timer = reader.read_u8()?;
+        let sound_timer = reader.read_u8()?;
+        let mut vram = [[0; SCHIP_WIDTH]; SCHIP_HEIGHT];
+        // Version 1 only had the 64x32 display
+        let (width, height) = if reader.version() < 2 {
+            (CHIP8_WIDTH, CHIP8_HEIGHT)
+        } else {
+            (SCHIP_WIDTH, SCHIP_HEIGHT)
+        };
+        for row in vram[.. height].iter_mut() {
+            row[.. width].copy_from_slice(reader.read_bytes(width)?);
+        }
+        let cpu_flags = reader.read_u8()?;
+        let selected_v = reader.read_u8()?;
+        let mut hires = false;
+        let mut rpl = self.rpl;
+        let mut exited = false;
+        if reader.version() >= 2 {
+            hires = reader.read_u8()? != 0;
+            rpl.copy_from_slice(reader.read_bytes(RPL_FLAGS_SIZE)?);
+            exited = reader.read_u8()? != 0;
+        }
+        let mut planes = 0x01;
+        let mut audio_pattern = [0x00; AUDIO_PATTERN_SIZE];
+        let mut pitch = DEFAULT_PITCH;
+        if reader.version() >= 3 {
+            planes = reader.read_u8()?;
+            audio_pattern.copy_from_slice(reader.read_bytes(AUDIO_PATTERN_SIZE)?);
+            pitch = reader.read_u8()?;
+        }
+        // Older snapshots keep the current generator
+        let mut seed = self.seed;
+        let mut rng = self.rng;
+        if reader.version() >= 4 {
+            seed = reader.read_u64()?;
+            rng = match Rng::from_state(reader.read_u64()?) {
+                Some(rng) => rng,
+                None => return Err(invalid_data("save state is corrupted"))
+            };
         }
-        else {
-            if self.delay_timer > 0 {
-                self.delay_timer -= 1;
-            }
 
-            if self.sound_timer > 0 {
-                self.sound_timer -= 1;
+        reader.finish()?;
 
-                beep_request = self.sound_timer == 0;
-            }
+        if sp as usize > STACK_SIZE || pc as usize >= memory_size ||
+           selected_v as usize >= NUM_REGISTERS {
+            return Err(invalid_data("save state is corrupted"));
+        }
+
+        self.variant = variant;
+        self.memory = memory;
+        self.stack = stack;
+        self.sp = sp;
+        self.v = v;
+        self.i = i;
+        self.pc = pc;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.vram = vram;
+        self.hires = hires;
+        self.cpu_flags = cpu_flags;
+        self.selected_v = selected_v;
+        self.rpl = rpl;
+        self.exited = exited;
+        self.planes = planes;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.seed = seed;
+        self.rng = rng;
+
+        Ok(())
+    }
+
+    /// Execute one instruction.
+    ///
+    /// A faulty instruction leaves the machine as it was before the tick,
+    /// with the program counter on the instruction.
+    pub fn tick(&mut self, keypad: [bool; KEYPAD_SIZE]) -> Result<Output, Chip8Error> {
+        self.keypad = keypad;
+        self.memory_writes.clear();
+
+        // Once the program has exited the machine stops
+        if self.exited {
+            return Ok(self.output(false));
+        }
+
+        self.cpu_flags &= !UPDATE_VRAM_BIT;
+        let mut executed = false;
+
+        // FX0A waits for the key events given to key_event, DXYN for
+        // tick_timers to signal the next frame
+        let waiting = WAITING_FOR_INPUT_BIT | WAITING_FOR_RELEASE_BIT | WAITING_FOR_VBLANK_BIT;
+        if (self.cpu_flags & waiting) == 0 {
+            self.check_memory(self.pc as usize, OPCODE_SIZE as usize)?;
 
             let opcode = self.read_opcode();
             let nibbles = (
-                (opcode & 0xF000) >> 12 as u8,
-                (opcode & 0x0F00) >> 8  as u8,
-                (opcode & 0x00F0) >> 4  as u8,
+                (opcode & 0xF000) >> 12,
+                (opcode & 0x0F00) >> 8,
+                (opcode & 0x00F0) >> 4,
                 (opcode & 0x000F)       as u8
             );
 
             let x   = nibbles.1 as u8;
             let y   = nibbles.2 as u8;
-            let n   = nibbles.3 as u8;
+            let n   = nibbles.3;
             let kk  = (opcode & 0x00FF) as u8;
-            let nnn = (opcode & 0x0FFF) as u16;
+            let nnn = opcode & 0x0FFF;
 
-            print!("Opcode = {:x?} ", opcode);
+            let xo = self.variant == Variant::XoChip;
 
             match nibbles {
+                (0x0,0x0,0xd,_) if xo   => self.exec_scu(n),
+                (0x5,_,_,0x2) if xo     => self.exec_save_vx_vy(x, y)?,
+                (0x5,_,_,0x3) if xo     => self.exec_load_vx_vy(x, y)?,
+                (0xf,0x0,0x0,0x0) if xo => self.exec_ld_i_long()?,
+                (0xf,_,0x0,0x1) if xo   => self.exec_plane(x),
+                (0xf,0x0,0x0,0x2) if xo => self.exec_audio()?,
+                (0xf,_,0x3,0xa) if xo   => self.exec_pitch(x),
                 (0x0,0x0,0xe,0x0) => self.exec_cls(),
-                (0x0,0x0,0xe,0xe) => self.exec_ret(),
+                (0x0,0x0,0xe,0xe) => self.exec_ret()?,
+                (0x0,0x0,0xc,_)   => self.exec_scd(n),
+                (0x0,0x0,0xf,0xb) => self.exec_scr(),
+                (0x0,0x0,0xf,0xc) => self.exec_scl(),
+                (0x0,0x0,0xf,0xd) => self.exec_exit(),
+                (0x0,0x0,0xf,0xe) => self.exec_low(),
+                (0x0,0x0,0xf,0xf) => self.exec_high(),
                 (0x1,_,_,_)       => self.exec_jp(nnn),
-                (0x2,_,_,_)       => self.exec_call(nnn),
+                (0x2,_,_,_)       => self.exec_call(nnn)?,
                 (0x3,_,_,_)       => self.exec_se_vx_byte(x, kk),
                 (0x4,_,_,_)       => self.exec_sne_vx_byte(x, kk),
-                (0x5,_,_,_)       => self.exec_se_vx_vy(x, y),
+                (0x5,_,_,0x0)     => self.exec_se_vx_vy(x, y),
                 (0x6,_,_,_)       => self.exec_ld_vx_byte(x, kk),
                 (0x7,_,_,_)       => self.exec_add_vx_byte(x, kk),
                 (0x8,_,_,0x0)     => self.exec_ld_vx_vy(x, y),
@@ -164,14 +633,14 @@ impl Processor {
                 (0x8,_,_,0x3)     => self.exec_xor_vx_vy(x, y),
                 (0x8,_,_,0x4)     => self.exec_add_vx_vy(x, y),
                 (0x8,_,_,0x5)     => self.exec_sub_vx_vy(x, y),
-                (0x8,_,_,0x6)     => self.exec_shr_vx_vy(x),
+                (0x8,_,_,0x6)     => self.exec_shr_vx_vy(x, y),
                 (0x8,_,_,0x7)     => self.exec_subn_vx_vy(x, y),
-                (0x8,_,_,0xe)     => self.exec_shl_vx_vy(x),
+                (0x8,_,_,0xe)     => self.exec_shl_vx_vy(x, y),
                 (0x9,_,_,0x0)     => self.exec_sne_vx_vy(x, y),
                 (0xa,_,_,_)       => self.exec_ld_i(nnn),
                 (0xb,_,_,_)       => self.exec_jp_v0(nnn),
                 (0xc,_,_,_)       => self.exec_rnd(x, kk),
-                (0xd,_,_,_)       => self.exec_drw(x, y, n),
+                (0xd,_,_,_)       => self.exec_drw(x, y, n)?,
                 (0xe,_,0x9,0xe)   => self.exec_skp(x),
                 (0xe,_,0xa,0x1)   => self.exec_sknp(x),
                 (0xf,_,0x0,0x7)   => self.exec_ld_vx_dt(x),
@@ -180,38 +649,174 @@ impl Processor {
                 (0xf,_,0x1,0x8)   => self.exec_ld_st_vx(x),
                 (0xf,_,0x1,0xe)   => self.exec_add_i_vx(x),
                 (0xf,_,0x2,0x9)   => self.exec_ld_f_vx(x),
-                (0xf,_,0x3,0x3)   => self.exec_ld_b_vx(x),
-                (0xf,_,0x5,0x5)   => self.exec_ld_i_vx(x),
-                (0xf,_,0x6,0x5)   => self.exec_ld_vx_i(x),
-                (_,_,_,_)         => self.increment_pc()
+                (0xf,_,0x3,0x0)   => self.exec_ld_hf_vx(x),
+                (0xf,_,0x3,0x3)   => self.exec_ld_b_vx(x)?,
+                (0xf,_,0x5,0x5)   => self.exec_ld_i_vx(x)?,
+                (0xf,_,0x6,0x5)   => self.exec_ld_vx_i(x)?,
+                (0xf,_,0x7,0x5)   => self.exec_ld_r_vx(x),
+                (0xf,_,0x8,0x5)   => self.exec_ld_vx_r(x),
+                (_,_,_,_)         => self.exec_unknown(opcode)?
             }
+
+            // Unless DXYN has to wait for the vertical blank
+            executed = !self.is_waiting_for_vblank();
+        }
+
+        Ok(self.output(executed))
+    }
+
+    /// Give FX0A a key going down or up, as reported by the input.
+    ///
+    /// FX0A stores the first key pressed once it waits, keys already held
+    /// do not count, and the program resumes once that key is released.
+    pub fn key_event(&mut self, event: KeyEvent) {
+        let selected = self.selected_v as usize;
+        if (self.cpu_flags & WAITING_FOR_INPUT_BIT) == WAITING_FOR_INPUT_BIT && event.pressed {
+            self.v[selected] = event.key & 0x0f;
+            self.cpu_flags = (self.cpu_flags & !WAITING_FOR_INPUT_BIT) | WAITING_FOR_RELEASE_BIT;
+        }
+        else if (self.cpu_flags & WAITING_FOR_RELEASE_BIT) == WAITING_FOR_RELEASE_BIT &&
+                !event.pressed && event.key & 0x0f == self.v[selected] {
+            self.cpu_flags &= !WAITING_FOR_RELEASE_BIT;
+        }
+    }
+
+    /// Decrement the delay and sound timers, to be called at 60 Hz
+    /// whatever the speed of the processor. This is also the vertical
+    /// blank a DXYN may be waiting for.
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
         }
 
-        Ok(Output {
+        // A DXYN waiting for the vertical blank can now draw
+        if (self.cpu_flags & WAITING_FOR_VBLANK_BIT) == WAITING_FOR_VBLANK_BIT {
+            self.cpu_flags = (self.cpu_flags & !WAITING_FOR_VBLANK_BIT) | VBLANK_BIT;
+        }
+    }
+
+    fn output(&self, executed: bool) -> Output {
+        Output {
             vram_changed: ((self.cpu_flags & UPDATE_VRAM_BIT) == UPDATE_VRAM_BIT),
-            beep_request,
+            beep_request: self.sound_timer > 0,
+            exited: self.exited,
+            hires: self.hires,
+            executed,
             vram: self.vram
-        })
+        }
     }
 
     /// __00E0 - CLS__
     /// Clear the display.
+    ///
+    /// On XO-CHIP only the selected planes are cleared.
     fn exec_cls(&mut self) {
-        self.vram = [[0x00; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        let mask = !self.planes;
+        for row in self.vram.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= mask;
+            }
+        }
 
         self.cpu_flags |= UPDATE_VRAM_BIT;
 
         self.increment_pc();
     }
 
+    /// __00Cn - SCD nibble__
+    /// Scroll the display down by n lines.
+    fn exec_scd(&mut self, n: u8) {
+        self.scroll(0, n as isize);
+
+        self.increment_pc();
+    }
+
+    /// __00Dn - SCU nibble__
+    /// Scroll the display up by n lines (XO-CHIP).
+    fn exec_scu(&mut self, n: u8) {
+        self.scroll(0, -(n as isize));
+
+        self.increment_pc();
+    }
+
+    /// __00FB - SCR__
+    /// Scroll the display right by 4 pixels.
+    fn exec_scr(&mut self) {
+        self.scroll(4, 0);
+
+        self.increment_pc();
+    }
+
+    /// __00FC - SCL__
+    /// Scroll the display left by 4 pixels.
+    fn exec_scl(&mut self) {
+        self.scroll(-4, 0);
+
+        self.increment_pc();
+    }
+
+    /// __00FD - EXIT__
+    /// Exit the interpreter.
+    ///
+    /// The machine stops executing instructions.
+    fn exec_exit(&mut self) {
+        self.exited = true;
+    }
+
+    /// __00FE - LOW__
+    /// Disable the high resolution mode, back to 64x32.
+    fn exec_low(&mut self) {
+        self.set_hires(false);
+
+        self.increment_pc();
+    }
+
+    /// __00FF - HIGH__
+    /// Enable the 128x64 high resolution mode.
+    fn exec_high(&mut self) {
+        self.set_hires(true);
+
+        self.increment_pc();
+    }
+
     /// __00EE - RET__
     /// Return from a subroutine
     ///
     /// The interpreter sets the program counter to the address
     /// at the top of the stack, then subtracts 1 from the stack pointer.
-    fn exec_ret(&mut self) {
+    fn exec_ret(&mut self) -> Result<(), Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow { pc: self.pc, opcode: self.read_opcode() });
+        }
+
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize];
+
+        Ok(())
+    }
+
+    /// __0nnn - SYS addr__ and any other unknown opcode
+    ///
+    /// SYS jumps to a machine code routine of the original interpreter,
+    /// which can't be emulated. The policy tells whether to stop, skip
+    /// the instruction or treat SYS as a no-op.
+    fn exec_unknown(&mut self, opcode: u16) -> Result<(), Chip8Error> {
+        self.unknown_opcodes.record(opcode, self.pc);
+
+        let sys = opcode & 0xF000 == 0x0000;
+        match self.unknown_opcode_policy {
+            UnknownOpcodePolicy::Skip => {},
+            UnknownOpcodePolicy::IgnoreSys if sys => {},
+            _ => return Err(Chip8Error::InvalidOpcode { pc: self.pc, opcode })
+        }
+
+        self.increment_pc();
+
+        Ok(())
     }
 
     /// __1nnn - JP addr__
@@ -227,12 +832,18 @@ impl Processor {
     ///
     /// The interpreter increments the stack pointer, then puts
     /// the current PC on the top of the stack. The PC is then set to nnn.
-    fn exec_call(&mut self, nnn: u16) {
-        self.stack[self.sp as usize] = self.pc + OPCODE_SIZE;
+    fn exec_call(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+        if self.sp as usize == STACK_SIZE {
+            return Err(Chip8Error::StackOverflow { pc: self.pc, opcode: self.read_opcode() });
+        }
+
+        self.stack[self.sp as usize] = self.pc.wrapping_add(OPCODE_SIZE);
 
         self.sp += 1;
 
         self.jump(nnn);
+
+        Ok(())
     }
 
     /// __3xkk - SE Vx, byte__
@@ -261,6 +872,38 @@ impl Processor {
         }
     }
 
+    /// __5xy2 - SAVE Vx, Vy__
+    /// Store registers Vx through Vy in memory starting at location I (XO-CHIP).
+    ///
+    /// The registers are stored in reverse order if x > y. I is not modified.
+    fn exec_save_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_memory(self.i as usize, register_range(x, y).count())?;
+
+        for (offset, register) in register_range(x, y).enumerate() {
+            self.write_memory(self.i as usize + offset, self.v[register]);
+        }
+
+        self.increment_pc();
+
+        Ok(())
+    }
+
+    /// __5xy3 - LOAD Vx, Vy__
+    /// Read registers Vx through Vy from memory starting at location I (XO-CHIP).
+    ///
+    /// The registers are read in reverse order if x > y. I is not modified.
+    fn exec_load_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_memory(self.i as usize, register_range(x, y).count())?;
+
+        for (offset, register) in register_range(x, y).enumerate() {
+            self.v[register] = self.memory[self.i as usize + offset];
+        }
+
+        self.increment_pc();
+
+        Ok(())
+    }
+
     /// __5xkk - SE Vx, Vy__
     /// Skip next instruction if Vx = Vy.
     ///
@@ -316,6 +959,9 @@ impl Processor {
     /// stores the result in Vx.
     fn exec_or_vx_vy(&mut self, x: u8, y: u8) {
         self.v[x as usize] |= self.v[y as usize];
+        if self.quirks.logic_resets_vf {
+            self.v[0xf] = 0;
+        }
 
         self.increment_pc();
     }
@@ -327,6 +973,9 @@ impl Processor {
     /// stores the result in Vx.
     fn exec_and_vx_vy(&mut self, x: u8, y: u8) {
         self.v[x as usize] &= self.v[y as usize];
+        if self.quirks.logic_resets_vf {
+            self.v[0xf] = 0;
+        }
 
         self.increment_pc();
     }
@@ -338,6 +987,9 @@ impl Processor {
     /// stores the result in Vx.
     fn exec_xor_vx_vy(&mut self, x: u8, y: u8) {
         self.v[x as usize] ^= self.v[y as usize];
+        if self.quirks.logic_resets_vf {
+            self.v[0xf] = 0;
+        }
 
         self.increment_pc();
     }
@@ -384,10 +1036,13 @@ impl Processor {
     ///
     /// If the least-significant bit of Vx is 1, then VF is
     /// set to 1, otherwise 0. Then Vx is divided by 2.
-    fn exec_shr_vx_vy(&mut self, x: u8) {
-        self.v[0xf] = self.v[x as usize] & 0x01;
+    ///
+    /// With the shift quirk, Vy is shifted and the result stored in Vx.
+    fn exec_shr_vx_vy(&mut self, x: u8, y: u8) {
+        let value = self.v[self.shift_source(x, y)];
 
-        self.v[x as usize] >>= 1;
+        self.v[x as usize] = value >> 1;
+        self.v[0xf] = value & 0x01;
 
         self.increment_pc();
     }
@@ -414,14 +1069,22 @@ impl Processor {
     ///
     /// If the most-significant bit of Vx is 1, then VF is
     /// set to 1, otherwise 0. Then Vx is multiplied by 2.
-    fn exec_shl_vx_vy(&mut self, x: u8) {
-        self.v[0xf] = (self.v[x as usize] & 0x80) >> 7;
+    ///
+    /// With the shift quirk, Vy is shifted and the result stored in Vx.
+    fn exec_shl_vx_vy(&mut self, x: u8, y: u8) {
+        let value = self.v[self.shift_source(x, y)];
 
-        self.v[x as usize] <<= 1;
+        self.v[x as usize] = value << 1;
+        self.v[0xf] = (value & 0x80) >> 7;
 
         self.increment_pc();
     }
 
+    /// Register shifted by 8xy6 and 8xye.
+    fn shift_source(&self, x: u8, y: u8) -> usize {
+        if self.quirks.shift_vy { y as usize } else { x as usize }
+    }
+
     /// __9xy0 - SNE Vx, Vy__
     /// Skip next instruction if Vx != Vy.
     ///
@@ -448,9 +1111,11 @@ impl Processor {
     /// __bnnn - JP V0, addr__
     /// Jump to location nnn + V0.
     ///
-    /// The program counter is set to nnn plus the value of V0.
+    /// The program counter is set to nnn plus the value of V0. With the
+    /// jump quirk, Vx is used instead, x being the highest nibble of nnn.
     fn exec_jp_v0(&mut self, nnn: u16) {
-        self.pc = nnn + self.v[0x0] as u16;
+        let x = if self.quirks.jump_vx { (nnn >> 8) as usize } else { 0x0 };
+        self.pc = nnn + self.v[x] as u16;
     }
 
     /// __cxkk - RND Vx, byte__
@@ -459,8 +1124,7 @@ impl Processor {
     /// The interpreter generates a random number from 0 to 255, which is
     /// then ANDed with the value kk. The results are stored in Vx.
     fn exec_rnd(&mut self, x: u8, kk: u8) {
-        let mut rng = rand::thread_rng();
-        self.v[x as usize] = rng.gen_range(0, 255) & kk;
+        self.v[x as usize] = self.rng.next_u8() & kk;
 
         self.increment_pc();
     }
@@ -474,31 +1138,76 @@ impl Processor {
     /// existing screen. If this causes any pixels to be erased, VF is
     /// set to 1, otherwise it is set to 0. If the sprite is positioned
     /// so part of it is outside the coordinates of the display, it wraps
-    /// around to the opposite side of the screen.
-    fn exec_drw(&mut self, x: u8, y: u8, n: u8) {
+    /// around to the opposite side of the screen, or is clipped with the
+    /// clipping quirk. The position itself always wraps.
+    ///
+    /// With n = 0 (SUPER-CHIP), a 16x16 sprite made of 32 bytes is drawn,
+    /// two bytes per row.
+    ///
+    /// On XO-CHIP the sprite is drawn on every selected plane, the data
+    /// for the second plane following the data for the first one.
+    ///
+    /// With the display wait quirk, the sprite is only drawn at the next
+    /// vertical blank, as on the VIP.
+    fn exec_drw(&mut self, x: u8, y: u8, n: u8) -> Result<(), Chip8Error> {
+        let (width, height) = self.screen_size();
+        let (rows, columns) = if n == 0 { (16, 16) } else { (n as usize, 8) };
+        let bytes_per_row = columns / 8;
+        let planes = self.planes;
+        self.check_memory(self.i as usize, planes.count_ones() as usize * rows * bytes_per_row)?;
+
+        // The VIP draws during the vertical blank: wait for the next one,
+        // the instruction is executed again once it has come
+        if self.quirks.display_wait {
+            if (self.cpu_flags & VBLANK_BIT) == 0 {
+                self.cpu_flags |= WAITING_FOR_VBLANK_BIT;
+                return Ok(());
+            }
+            self.cpu_flags &= !VBLANK_BIT;
+        }
+
+        let mut sprite = self.i as usize;
+        let clip = self.quirks.clip_sprites;
+        let vx = self.v[x as usize] as usize % width;
+        let vy = self.v[y as usize] as usize % height;
+
         self.v[0x0f] = 0;
-        for byte in 0..n {
-            let y = (self.v[y as usize] as usize + byte as usize) % CHIP8_HEIGHT;
-            for bit in 0..8 {
-                let x = (self.v[x as usize] as usize + bit) % CHIP8_WIDTH;
-                let color = (self.memory[self.i as usize + byte as usize] >> (7 - bit)) & 1;
-
-                self.v[0x0f] |= color & self.vram[y][x];
-                self.vram[y][x] ^= color;
+        for plane in (0 .. NUM_PLANES).map(|plane| 1 << plane).filter(|plane| planes & plane != 0) {
+            for row in 0..rows {
+                if clip && vy + row >= height {
+                    break;
+                }
+                let y = (vy + row) % height;
+                for column in 0..columns {
+                    if clip && vx + column >= width {
+                        break;
+                    }
+                    let x = (vx + column) % width;
+                    let byte = self.memory[sprite + row * bytes_per_row + column / 8];
+                    let color = ((byte >> (7 - column % 8)) & 1) * plane;
+
+                    self.v[0x0f] |= (color & self.vram[y][x] != 0) as u8;
+                    self.vram[y][x] ^= color;
+                }
             }
+
+            sprite += rows * bytes_per_row;
         }
 
         self.cpu_flags |= UPDATE_VRAM_BIT;
         self.increment_pc();
+
+        Ok(())
     }
 
     /// __ex9e - SKP Vx__
     /// Skip next instruction if key with the value of Vx is pressed.
     ///
     /// Checks the keyboard, and if the key corresponding to the value of Vx is
-    /// currently in the down position, PC is increased by 2.
+    /// currently in the down position, PC is increased by 2. As on the VIP,
+    /// only the low nibble of Vx is used.
     fn exec_skp(&mut self, x: u8) {
-        if self.keypad[self.v[x as usize] as usize] {
+        if self.is_key_pressed(x) {
             self.skip();
         } else {
             self.increment_pc();
@@ -509,15 +1218,21 @@ impl Processor {
     /// Skip next instruction if key with the value of Vx is not pressed.
     ///
     /// Checks the keyboard, and if the key corresponding to the value of Vx is
-    /// currently in the up position, PC is increased by 2.
+    /// currently in the up position, PC is increased by 2. As on the VIP,
+    /// only the low nibble of Vx is used.
     fn exec_sknp(&mut self, x: u8) {
-        if !(self.keypad[self.v[x as usize] as usize]) {
+        if !self.is_key_pressed(x) {
             self.skip();
         } else {
             self.increment_pc();
         }
     }
 
+    // Key named by the low nibble of Vx, the keypad only has 16 keys
+    fn is_key_pressed(&self, x: u8) -> bool {
+        self.keypad[(self.v[x as usize] & 0x0f) as usize]
+    }
+
     /// __fx07 - LD Vx, DT__
     /// Set Vx = delay timer value.
     ///
@@ -532,7 +1247,9 @@ impl Processor {
     /// Wait for a key press, store the value of the key in Vx
     ///
     /// All execution stops until a key is pressed, then the value
-    /// of that key is stored in Vx.
+    /// of that key is stored in Vx. As on the VIP, execution resumes
+    /// once the key is released. The timers keep counting down. The keys
+    /// come from `key_event`.
     fn exec_ld_vx_k(&mut self, x: u8) {
         self.cpu_flags |= WAITING_FOR_INPUT_BIT;
         self.selected_v = x;
@@ -564,9 +1281,12 @@ impl Processor {
     /// Set I = I + Vx.
     ///
     /// The values of I and Vx are added, and the results are stored in I.
+    /// With the add_i_overflow quirk, VF is set if I goes past 0xF00.
     fn exec_add_i_vx(&mut self, x: u8) {
-        self.i += self.v[x as usize] as u16;
-        self.v[0xf] = if self.i > 0x0F00 { 1 } else { 0 };
+        self.i = self.i.wrapping_add(self.v[x as usize] as u16);
+        if self.quirks.add_i_overflow {
+            self.v[0xf] = if self.i > 0x0F00 { 1 } else { 0 };
+        }
 
         self.increment_pc();
     }
@@ -582,24 +1302,85 @@ impl Processor {
         self.increment_pc();
     }
 
+    /// __f000 nnnn - LD I, LONG addr__
+    /// Set I = nnnn (XO-CHIP).
+    ///
+    /// The 16-bit address is stored in the two bytes following the opcode.
+    fn exec_ld_i_long(&mut self) -> Result<(), Chip8Error> {
+        let addr = self.pc as usize + OPCODE_SIZE as usize;
+        self.check_memory(addr, 2)?;
+        self.i = ((self.memory[addr] as u16) << 8) | self.memory[addr + 1] as u16;
+
+        self.pc = self.pc.wrapping_add(2 * OPCODE_SIZE);
+
+        Ok(())
+    }
+
+    /// __fn01 - PLANE n__
+    /// Select the planes affected by drawing (XO-CHIP).
+    ///
+    /// n is a bit mask, 1 for the first plane, 2 for the second, 3 for both.
+    fn exec_plane(&mut self, n: u8) {
+        self.planes = n & 0x03;
+
+        self.increment_pc();
+    }
+
+    /// __f002 - AUDIO__
+    /// Load the 16-byte audio pattern buffer from memory at location I (XO-CHIP).
+    fn exec_audio(&mut self) -> Result<(), Chip8Error> {
+        let i = self.i as usize;
+        self.check_memory(i, AUDIO_PATTERN_SIZE)?;
+        self.audio_pattern.copy_from_slice(&self.memory[i .. i + AUDIO_PATTERN_SIZE]);
+
+        self.increment_pc();
+
+        Ok(())
+    }
+
+    /// __fx3a - PITCH Vx__
+    /// Set the playback rate of the audio pattern to Vx (XO-CHIP).
+    ///
+    /// The rate is 4000 * 2 ^ ((Vx - 64) / 48) Hz.
+    fn exec_pitch(&mut self, x: u8) {
+        self.pitch = self.v[x as usize];
+
+        self.increment_pc();
+    }
+
+    /// __fx30 - LD HF, Vx__
+    /// Set I = location of the large sprite for digit Vx.
+    ///
+    /// The value of I is set to the location for the 8x10
+    /// SUPER-CHIP sprite corresponding to the value of Vx.
+    fn exec_ld_hf_vx(&mut self, x: u8) {
+        self.i = (LARGE_FONT_AREA_START as u16) + ((self.v[x as usize] & 0x0f) as u16) * 10;
+
+        self.increment_pc();
+    }
+
     /// __fx33 - LD B, Vx__
     /// Store BCD representation of Vx in memory locations I, I+1, and I+2.
     ///
     /// The interpreter takes the decimal value of Vx, and places
     /// the hundreds digit in memory at location in I, the tens
     /// digit at location I+1, and the ones digit at location I+2.
-    fn exec_ld_b_vx(&mut self, x: u8) {
+    fn exec_ld_b_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_memory(self.i as usize, 3)?;
+
         let value = self.v[x as usize];
 
         let hundreds = value / 100;
         let tens = (value % 100) / 10;
         let ones = value % 10;
 
-        self.memory[self.i as usize]     = hundreds;
-        self.memory[self.i as usize + 1] = tens;
-        self.memory[self.i as usize + 2] = ones;
+        self.write_memory(self.i as usize,     hundreds);
+        self.write_memory(self.i as usize + 1, tens);
+        self.write_memory(self.i as usize + 2, ones);
 
         self.increment_pc();
+
+        Ok(())
     }
 
     /// __fx55 - LD [I], Vx__
@@ -607,13 +1388,18 @@ impl Processor {
     ///
     /// The interpreter copies the values of registers V0 through
     /// Vx into memory, starting at the address in I.
-    fn exec_ld_i_vx(&mut self, x: u8) {
+    fn exec_ld_i_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
         let limit = x as usize;
+        self.check_memory(self.i as usize, limit + 1)?;
+
         for i in 0 ..= limit {
-            self.memory[self.i as usize + i] = self.v[i];
+            self.write_memory(self.i as usize + i, self.v[i]);
         }
+        self.increment_i(x);
 
         self.increment_pc();
+
+        Ok(())
     }
 
     /// __fx65 - LD Vx, [I]__
@@ -621,25 +1407,118 @@ impl Processor {
     ///
     /// The interpreter reads values from memory starting at location I
     /// into registers V0 through Vx.
-    fn exec_ld_vx_i(&mut self, x: u8) {
+    fn exec_ld_vx_i(&mut self, x: u8) -> Result<(), Chip8Error> {
         let limit = x as usize;
+        self.check_memory(self.i as usize, limit + 1)?;
+
         for i in 0 ..= limit {
             self.v[i] = self.memory[self.i as usize + i];
         }
+        self.increment_i(x);
+
+        self.increment_pc();
+
+        Ok(())
+    }
+
+    /// Move I after Fx55/Fx65, as the load/store quirk says.
+    fn increment_i(&mut self, x: u8) {
+        let increment = match self.quirks.load_store {
+            IndexIncrement::Unchanged  => 0,
+            IndexIncrement::ByX        => x as u16,
+            IndexIncrement::ByXPlusOne => x as u16 + 1
+        };
+        self.i = self.i.wrapping_add(increment);
+    }
+
+    /// __fx75 - LD R, Vx__
+    /// Store registers V0 through Vx in the RPL user flags.
+    fn exec_ld_r_vx(&mut self, x: u8) {
+        let limit = x as usize;
+        self.rpl[..= limit].copy_from_slice(&self.v[..= limit]);
+
+        self.increment_pc();
+    }
+
+    /// __fx85 - LD Vx, R__
+    /// Read registers V0 through Vx from the RPL user flags.
+    fn exec_ld_vx_r(&mut self, x: u8) {
+        let limit = x as usize;
+        self.v[..= limit].copy_from_slice(&self.rpl[..= limit]);
 
         self.increment_pc();
     }
 
+    /// Move the selected planes of the display by dx pixels to the right
+    /// and dy pixels down, filling the uncovered area with blank pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = self.screen_size();
+        let source = self.vram;
+
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let (from_x, from_y) = (x as isize - dx, y as isize - dy);
+                let inside = from_x >= 0 && from_x < width as isize && from_y >= 0 && from_y < height as isize;
+                let pixel = if inside { source[from_y as usize][from_x as usize] } else { 0x00 };
+
+                self.vram[y][x] = (source[y][x] & !self.planes) | (pixel & self.planes);
+            }
+        }
+
+        self.cpu_flags |= UPDATE_VRAM_BIT;
+    }
+
+    /// Switch between the 64x32 and 128x64 modes, clearing the display.
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.vram = [[0x00; SCHIP_WIDTH]; SCHIP_HEIGHT];
+
+        self.cpu_flags |= UPDATE_VRAM_BIT;
+    }
+
+    /// Size of the display in the current mode.
+    fn screen_size(&self) -> (usize, usize) {
+        if self.hires {
+            (SCHIP_WIDTH, SCHIP_HEIGHT)
+        } else {
+            (CHIP8_WIDTH, CHIP8_HEIGHT)
+        }
+    }
+
+    /// Store a byte in memory, keeping track of the address for watchpoints.
+    fn write_memory(&mut self, addr: usize, value: u8) {
+        self.memory[addr] = value;
+        self.memory_writes.push(addr as u16);
+    }
+
     /// Return the opcode currently pointed from the program counter.
     fn read_opcode(&self) -> u16 {
-        ((self.memory[self.pc as usize] as u16) << 8) |
-          self.memory[(self.pc + 1) as usize] as u16
+        self.read_opcode_at(self.pc)
+    }
+
+    /// Bytes past the end of memory read as 0.
+    fn read_opcode_at(&self, addr: u16) -> u16 {
+        let byte = |addr: usize| self.memory.get(addr).copied().unwrap_or(0) as u16;
+
+        (byte(addr as usize) << 8) | byte(addr as usize + 1)
+    }
+
+    /// Fail unless `len` bytes starting at `addr` are inside memory.
+    fn check_memory(&self, addr: usize, len: usize) -> Result<(), Chip8Error> {
+        if addr + len > self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                pc: self.pc,
+                opcode: self.read_opcode(),
+                address: addr.max(self.memory.len())
+            });
+        }
+
+        Ok(())
     }
 
     /// Increment the program counter.
     fn increment_pc(&mut self) {
-        self.pc += 2;
-        println!("PC = {:x?}", self.pc);
+        self.pc = self.pc.wrapping_add(OPCODE_SIZE);
     }
 
     /// Jump to the specified address.
@@ -649,12 +1528,443 @@ impl Processor {
     /// * `addr` - u16 containing the target address.
     fn jump(&mut self, addr: u16) {
         self.pc = addr;
-        println!("PC = {:x?}", self.pc);
     }
 
     /// Skip the next opcode.
+    ///
+    /// On XO-CHIP the 4-byte F000 instruction is skipped as a whole.
     fn skip(&mut self) {
-        self.pc += 2 * OPCODE_SIZE;
-        println!("PC = {:x?}", self.pc);
+        let next = self.pc.wrapping_add(OPCODE_SIZE);
+        self.pc = next.wrapping_add(OPCODE_SIZE);
+
+        if self.variant == Variant::XoChip && self.read_opcode_at(next) == 0xF000 {
+            self.pc = self.pc.wrapping_add(OPCODE_SIZE);
+        }
+    }
+}
+
+/// Registers from Vx to Vy, in reverse order if x > y.
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+    let (x, y) = (x as usize, y as usize);
+    if x <= y {
+        Box::new(x ..= y)
+    } else {
+        Box::new((y ..= x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run a program for a number of instructions
+    fn run(program: &[u8], keypad: [bool; KEYPAD_SIZE], ticks: usize) -> Processor {
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+        processor.load(program).unwrap();
+        for _ in 0 .. ticks {
+            processor.tick(keypad).unwrap();
+        }
+
+        processor
+    }
+
+    // Run a program with the given quirks
+    fn run_with(quirks: Quirks, program: &[u8], ticks: usize) -> Processor {
+        let mut processor = Processor::new(Variant::Chip8, quirks);
+        processor.load(program).unwrap();
+        for _ in 0 .. ticks {
+            processor.tick([false; KEYPAD_SIZE]).unwrap();
+        }
+
+        processor
+    }
+
+    #[test]
+    fn skp_uses_the_low_nibble_of_vx() {
+        // LD V0, FF / SKP V0
+        let program = [0x60, 0xff, 0xe0, 0x9e];
+        assert_eq!(run(&program, [false; KEYPAD_SIZE], 2).pc(), 0x204);
+
+        let mut keypad = [false; KEYPAD_SIZE];
+        keypad[0xf] = true;
+        assert_eq!(run(&program, keypad, 2).pc(), 0x206);
+    }
+
+    #[test]
+    fn sknp_uses_the_low_nibble_of_vx() {
+        // LD V0, FF / SKNP V0
+        let program = [0x60, 0xff, 0xe0, 0xa1];
+        assert_eq!(run(&program, [false; KEYPAD_SIZE], 2).pc(), 0x206);
+    }
+
+    // LD V3, K / LD V1, 01
+    const WAIT_FOR_KEY: [u8; 4] = [0xf3, 0x0a, 0x61, 0x01];
+
+    fn key(key: u8, pressed: bool) -> KeyEvent {
+        KeyEvent { key, pressed }
+    }
+
+    #[test]
+    fn fx0a_stores_the_key_pressed() {
+        let mut processor = run(&WAIT_FOR_KEY, [false; KEYPAD_SIZE], 1);
+        assert!(!processor.tick([false; KEYPAD_SIZE]).unwrap().executed);
+
+        processor.key_event(key(0x5, true));
+        assert_eq!(processor.v(0x3), 0x5);
+    }
+
+    #[test]
+    fn fx0a_resumes_once_the_key_is_released() {
+        let mut keypad = [false; KEYPAD_SIZE];
+        let mut processor = run(&WAIT_FOR_KEY, keypad, 1);
+        processor.key_event(key(0x5, true));
+        keypad[0x5] = true;
+        processor.tick(keypad).unwrap();
+        assert_eq!(processor.v(0x1), 0x0);
+
+        // Another key going up does not count
+        processor.key_event(key(0x6, false));
+        processor.tick(keypad).unwrap();
+        assert_eq!(processor.v(0x1), 0x0);
+
+        processor.key_event(key(0x5, false));
+        keypad[0x5] = false;
+        assert!(processor.tick(keypad).unwrap().executed);
+        assert_eq!(processor.v(0x1), 0x1);
+    }
+
+    #[test]
+    fn fx0a_ignores_keys_held_before() {
+        let mut keypad = [false; KEYPAD_SIZE];
+        keypad[0x5] = true;
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+        processor.load(&WAIT_FOR_KEY).unwrap();
+        processor.key_event(key(0x5, true));
+        processor.tick(keypad).unwrap();
+
+        // Held all along, then released
+        processor.tick(keypad).unwrap();
+        processor.key_event(key(0x5, false));
+        processor.tick([false; KEYPAD_SIZE]).unwrap();
+        assert_eq!((processor.pc(), processor.v(0x3)), (0x202, 0x0));
+
+        processor.key_event(key(0x7, true));
+        assert_eq!(processor.v(0x3), 0x7);
+    }
+
+    #[test]
+    fn timers_run_while_fx0a_waits() {
+        // LD V0, 0A / LD DT, V0 / LD V1, K
+        let mut processor = run(&[0x60, 0x0a, 0xf0, 0x15, 0xf1, 0x0a], [false; KEYPAD_SIZE], 3);
+        for _ in 0 .. 4 {
+            processor.tick([false; KEYPAD_SIZE]).unwrap();
+            processor.tick_timers();
+        }
+
+        assert_eq!((processor.pc(), processor.delay_timer()), (0x206, 0x6));
+    }
+
+    #[test]
+    fn shift_vy_quirk() {
+        // LD V0, 81 / LD V1, 02 / SHR V1, V0
+        let program = [0x60, 0x81, 0x61, 0x02, 0x81, 0x06];
+        let shifted = |shift_vy| {
+            let processor = run_with(Quirks { shift_vy, ..Quirks::default() }, &program, 3);
+            (processor.v(0x1), processor.v(0xf))
+        };
+
+        assert_eq!(shifted(true), (0x40, 0x1));
+        assert_eq!(shifted(false), (0x01, 0x0));
+    }
+
+    #[test]
+    fn load_store_quirk() {
+        // LD I, 300 / LD [I], V2
+        let program = [0xa3, 0x00, 0xf2, 0x55];
+        let index = |load_store| run_with(Quirks { load_store, ..Quirks::default() }, &program, 2).i();
+
+        assert_eq!(index(IndexIncrement::Unchanged), 0x300);
+        assert_eq!(index(IndexIncrement::ByX), 0x302);
+        assert_eq!(index(IndexIncrement::ByXPlusOne), 0x303);
+    }
+
+    #[test]
+    fn jump_vx_quirk() {
+        // LD V0, 10 / LD V2, 20 / JP V0, 240
+        let program = [0x60, 0x10, 0x62, 0x20, 0xb2, 0x40];
+        let target = |jump_vx| run_with(Quirks { jump_vx, ..Quirks::default() }, &program, 3).pc();
+
+        assert_eq!(target(true), 0x260);
+        assert_eq!(target(false), 0x250);
+    }
+
+    #[test]
+    fn clip_sprites_quirk() {
+        // LD V0, 3C / LD I, 208 / DRW V0, V1, 1 / JP 206 / db FF
+        let program = [0x60, 0x3c, 0xa2, 0x08, 0xd0, 0x11, 0x12, 0x06, 0xff];
+        let drawn = |clip_sprites| {
+            let processor = run_with(Quirks { clip_sprites, ..Quirks::default() }, &program, 3);
+            (processor.vram()[0][63], processor.vram()[0][0])
+        };
+
+        assert_eq!(drawn(true), (1, 0));
+        assert_eq!(drawn(false), (1, 1));
+    }
+
+    #[test]
+    fn logic_resets_vf_quirk() {
+        // LD VF, 05 / OR V0, V1
+        let program = [0x6f, 0x05, 0x80, 0x11];
+        let flag = |logic_resets_vf| run_with(Quirks { logic_resets_vf, ..Quirks::default() }, &program, 2).v(0xf);
+
+        assert_eq!(flag(true), 0x0);
+        assert_eq!(flag(false), 0x5);
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        // DRW V0, V0, 5
+        let program = [0xd0, 0x05];
+        let drawn = |display_wait| {
+            let processor = run_with(Quirks { display_wait, ..Quirks::default() }, &program, 1);
+            (processor.pc(), processor.vram()[0][0])
+        };
+
+        assert_eq!(drawn(true), (0x200, 0));
+        assert_eq!(drawn(false), (0x202, 1));
+    }
+
+    #[test]
+    fn drw_waits_for_the_next_vertical_blank() {
+        // DRW V0, V1, 1 / ADD V0, 08 / JP 200
+        let quirks = Quirks { display_wait: true, ..Quirks::default() };
+        let mut processor = run_with(quirks, &[0xd0, 0x11, 0x70, 0x08, 0x12, 0x00], 0);
+        let frame = |processor: &mut Processor| {
+            let executed = (0 .. 10).filter(|_| processor.tick([false; KEYPAD_SIZE]).unwrap().executed).count();
+            processor.tick_timers();
+            (executed, processor.pc(), processor.v(0))
+        };
+
+        // The first DRW waits for the end of the frame, then one DRW is
+        // drawn per frame
+        assert_eq!(frame(&mut processor), (0, 0x200, 0x00));
+        assert_eq!(frame(&mut processor), (3, 0x200, 0x08));
+        assert_eq!(frame(&mut processor), (3, 0x200, 0x10));
+        assert_eq!(&processor.vram()[0][.. 20], &[
+            1, 1, 1, 1, 0, 0, 0, 0,
+            1, 1, 1, 1, 0, 0, 0, 0,
+            0, 0, 0, 0
+        ]);
+    }
+
+    #[test]
+    fn faulty_drw_leaves_the_machine_unchanged() {
+        // DRW V0, V0, 5 waits for the vertical blank, then I is moved to
+        // the end of memory
+        let quirks = Quirks { display_wait: true, ..Quirks::default() };
+        let mut processor = run_with(quirks, &[0xd0, 0x05], 1);
+        processor.tick_timers();
+        processor.set_i(0xfff);
+        let before = processor.save_state();
+
+        assert!(processor.tick([false; KEYPAD_SIZE]).is_err());
+        assert_eq!(processor.save_state(), before);
+    }
+
+    // Program counter after running SYS 123 / 5XY1 / LD V0, 01 under a
+    // policy, or the error it stopped on
+    fn run_unknown(policy: UnknownOpcodePolicy) -> Result<u16, Chip8Error> {
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+        processor.set_unknown_opcode_policy(policy);
+        processor.load(&[0x01, 0x23, 0x5f, 0x01, 0x60, 0x01]).unwrap();
+        for _ in 0 .. 3 {
+            processor.tick([false; KEYPAD_SIZE])?;
+        }
+
+        Ok(processor.pc())
+    }
+
+    #[test]
+    fn unknown_opcode_policies() {
+        match run_unknown(UnknownOpcodePolicy::Halt) {
+            Err(Chip8Error::InvalidOpcode { pc: 0x200, opcode: 0x0123 }) => {},
+            result => panic!("halt gave {:?}", result)
+        }
+        match run_unknown(UnknownOpcodePolicy::IgnoreSys) {
+            Err(Chip8Error::InvalidOpcode { pc: 0x202, opcode: 0x5f01 }) => {},
+            result => panic!("sys gave {:?}", result)
+        }
+        assert_eq!(run_unknown(UnknownOpcodePolicy::Skip).ok(), Some(0x206));
+    }
+
+    #[test]
+    fn unknown_opcodes_are_reported() {
+        // 5XY1 / SYS 123 / 5XY1 / JP 200
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+        processor.set_unknown_opcode_policy(UnknownOpcodePolicy::Skip);
+        processor.load(&[0x5f, 0x01, 0x01, 0x23, 0x5f, 0x01, 0x12, 0x00]).unwrap();
+        for _ in 0 .. 8 {
+            processor.tick([false; KEYPAD_SIZE]).unwrap();
+        }
+
+        let report: Vec<(u16, Vec<u16>)> = processor.unknown_opcodes().iter()
+            .map(|(opcode, addresses)| (opcode, addresses.iter().copied().collect()))
+            .collect();
+        assert_eq!(report, vec![(0x0123, vec![0x202]), (0x5f01, vec![0x200, 0x204])]);
+    }
+
+    #[test]
+    fn add_i_sets_vf_past_f00_by_default() {
+        // LD VF, 05 / LD I, EFF / LD V0, 01 / ADD I, V0 / ADD I, V0
+        let program = [0x6f, 0x05, 0xae, 0xff, 0x60, 0x01, 0xf0, 0x1e, 0xf0, 0x1e];
+        assert_eq!(run_with(Quirks::default(), &program, 4).v(0xf), 0x00);
+        assert_eq!(run_with(Quirks::default(), &program, 5).v(0xf), 0x01);
+
+        let quirks = Quirks { add_i_overflow: false, ..Quirks::default() };
+        let processor = run_with(quirks, &program, 5);
+        assert_eq!((processor.i(), processor.v(0xf)), (0xf01, 0x05));
+    }
+
+    #[test]
+    fn saved_state_loads_back_to_the_same_state() {
+        // LD I, 300 / RND V1, FF / LD [I], V1 / ADD I, V1 / DRW V1, V1, 5 / JP 202
+        let program = [0xa3, 0x00, 0xc1, 0xff, 0xf1, 0x55, 0xf1, 0x1e, 0xd1, 0x15, 0x12, 0x02];
+        let state = run(&program, [false; KEYPAD_SIZE], 50).save_state();
+
+        let mut processor = Processor::new(Variant::XoChip, Quirks::default());
+        processor.load_state(&state).unwrap();
+
+        assert_eq!(processor.save_state(), state);
+    }
+
+    #[test]
+    fn version_1_state_loads() {
+        // Magic, version, then the fields of the first format
+        let mut state = b"C8ST\x01".to_vec();
+        let mut memory = Processor::initial_memory(Variant::Chip8);
+        memory[0x202 .. 0x204].copy_from_slice(&[0x73, 0x01]);
+        state.extend_from_slice(&memory);
+        state.extend_from_slice(&[0; STACK_SIZE * 2]);
+        state.extend_from_slice(&[0x00, 0x00]);
+        state.extend_from_slice(&[0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        state.extend_from_slice(&[0x03, 0x00, 0x02, 0x02, 5, 0]);
+        let mut vram = vec![0; CHIP8_WIDTH * CHIP8_HEIGHT];
+        vram[0] = 1;
+        state.extend_from_slice(&vram);
+        state.extend_from_slice(&[0, 0]);
+
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+        processor.load_state(&state).unwrap();
+        assert_eq!((processor.pc(), processor.i(), processor.delay_timer()), (0x202, 0x300, 5));
+        assert_eq!(processor.vram()[0][0], 1);
+        assert!(!processor.hires());
+
+        // ADD V3, 1
+        processor.tick([false; KEYPAD_SIZE]).unwrap();
+        assert_eq!(processor.v(3), 8);
+    }
+
+    #[test]
+    fn rom_can_fill_the_program_area() {
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+        assert!(processor.load(&[0x00; 0xe00]).is_ok());
+        assert!(processor.load(&[0x00; 0xe01]).is_err());
+    }
+
+    // Pixels set in the top-left corner of the display, one row per line
+    fn corner(processor: &Processor, size: usize) -> Vec<Vec<u8>> {
+        processor.vram()[.. size].iter().map(|row| row[.. size].to_vec()).collect()
+    }
+
+    #[test]
+    fn scroll_down_in_both_resolutions() {
+        // DRW V0, V0, 1 / SCD 2, the top row of the 0 is 11110000
+        let low = run(&[0xd0, 0x01, 0x00, 0xc2], [false; KEYPAD_SIZE], 2);
+        assert_eq!((low.vram()[0][0], low.vram()[2][0]), (0, 1));
+
+        // HIGH / DRW V0, V0, 1 / SCD 2
+        let high = run(&[0x00, 0xff, 0xd0, 0x01, 0x00, 0xc2], [false; KEYPAD_SIZE], 3);
+        assert_eq!((high.vram()[0][0], high.vram()[2][0]), (0, 1));
+
+        // Lines scrolled past the bottom of the low resolution screen are
+        // lost: LD V1, 1F / DRW V0, V1, 1 / SCD 2
+        let lost = run(&[0x61, 0x1f, 0xd0, 0x11, 0x00, 0xc2], [false; KEYPAD_SIZE], 3);
+        assert!(lost.vram().iter().all(|row| row.iter().all(|&pixel| pixel == 0)));
+    }
+
+    #[test]
+    fn scroll_right_and_left_in_both_resolutions() {
+        // DRW V0, V0, 1 / SCR
+        let right = run(&[0xd0, 0x01, 0x00, 0xfb], [false; KEYPAD_SIZE], 2);
+        assert_eq!(&right.vram()[0][.. 9], &[0, 0, 0, 0, 1, 1, 1, 1, 0]);
+
+        // HIGH / DRW V0, V0, 1 / SCR / SCL / SCL
+        let program = [0x00, 0xff, 0xd0, 0x01, 0x00, 0xfb, 0x00, 0xfc, 0x00, 0xfc];
+        let back = run(&program, [false; KEYPAD_SIZE], 4);
+        assert_eq!(&back.vram()[0][.. 9], &[1, 1, 1, 1, 0, 0, 0, 0, 0]);
+        let left = run(&program, [false; KEYPAD_SIZE], 5);
+        assert_eq!(&left.vram()[0][.. 9], &[0; 9]);
+
+        // Pixels scrolled past the right edge are lost: LD V0, 3C /
+        // DRW V0, V1, 1 / SCR
+        let lost = run(&[0x60, 0x3c, 0xd0, 0x11, 0x00, 0xfb], [false; KEYPAD_SIZE], 3);
+        assert!(lost.vram().iter().all(|row| row.iter().all(|&pixel| pixel == 0)));
+    }
+
+    #[test]
+    fn large_sprites_collide() {
+        // HIGH / LD I, 20A / DRW V0, V0, 0 / DRW V0, V0, 0 / JP 208, then
+        // a 16x16 square
+        let mut program = vec![0x00, 0xff, 0xa2, 0x0a, 0xd0, 0x00, 0xd0, 0x00, 0x12, 0x08];
+        program.extend_from_slice(&[0xff; 32]);
+
+        let drawn = run(&program, [false; KEYPAD_SIZE], 3);
+        let mut square = vec![vec![1; 16]; 16];
+        square.iter_mut().for_each(|row| row.push(0));
+        square.push(vec![0; 17]);
+        assert_eq!(corner(&drawn, 17), square);
+        assert_eq!(drawn.v(0xf), 0);
+
+        let erased = run(&program, [false; KEYPAD_SIZE], 4);
+        assert_eq!(corner(&erased, 17), vec![vec![0; 17]; 17]);
+        assert_eq!(erased.v(0xf), 1);
+    }
+
+    #[test]
+    fn changing_resolution_clears_the_screen() {
+        // DRW V0, V0, 5 / HIGH / DRW V0, V0, 5 / LOW
+        let program = [0xd0, 0x05, 0x00, 0xff, 0xd0, 0x05, 0x00, 0xfe];
+        let high = run(&program, [false; KEYPAD_SIZE], 2);
+        assert!(high.hires());
+        assert_eq!(corner(&high, 8), vec![vec![0; 8]; 8]);
+
+        let low = run(&program, [false; KEYPAD_SIZE], 4);
+        assert!(!low.hires());
+        assert_eq!(corner(&low, 8), vec![vec![0; 8]; 8]);
+    }
+
+    #[test]
+    fn large_font_and_exit() {
+        // LD V0, 03 / LD HF, V0 / EXIT
+        let mut processor = run(&[0x60, 0x03, 0xf0, 0x30, 0x00, 0xfd], [false; KEYPAD_SIZE], 3);
+        assert_eq!(processor.i(), LARGE_FONT_AREA_START as u16 + 30);
+
+        let output = processor.tick([false; KEYPAD_SIZE]).unwrap();
+        assert!(output.exited && !output.executed);
+        assert_eq!(processor.pc(), 0x204);
+    }
+
+    #[test]
+    fn rpl_flags_round_trip() {
+        // LD V0, 11 / LD V1, 22 / LD V2, 33 / LD R, V2 / LD V0, 00 /
+        // LD V1, 00 / LD V2, 00 / LD V1, R
+        let program = [
+            0x60, 0x11, 0x61, 0x22, 0x62, 0x33, 0xf2, 0x75,
+            0x60, 0x00, 0x61, 0x00, 0x62, 0x00, 0xf1, 0x85
+        ];
+        let processor = run(&program, [false; KEYPAD_SIZE], 8);
+
+        assert_eq!(&processor.rpl_flags()[.. 4], &[0x11, 0x22, 0x33, 0x00]);
+        assert_eq!((processor.v(0), processor.v(1), processor.v(2)), (0x11, 0x22, 0x00));
     }
 }
@@ -0,0 +1,151 @@
+/// Behaviour of the instructions that differ between interpreters.
+///
+/// Games expect the semantics of the interpreter they were written for,
+/// the presets cover the common ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    /// 8xy6/8xye shift Vy and store the result in Vx, instead of
+    /// shifting Vx in place
+    pub shift_vy: bool,
+    /// How Fx55/Fx65 change I
+    pub load_store: IndexIncrement,
+    /// Bnnn jumps to nnn + Vx, x being the highest nibble of nnn,
+    /// instead of nnn + V0
+    pub jump_vx: bool,
+    /// Sprites are clipped at the edges of the screen instead of
+    /// wrapping around
+    pub clip_sprites: bool,
+    /// 8xy1, 8xy2 and 8xy3 reset VF
+    pub logic_resets_vf: bool,
+    /// Dxyn waits for the next 60 Hz frame before drawing, so that at most
+    /// one sprite is drawn per frame
+    pub display_wait: bool,
+    /// Fx1e sets VF to 1 when I goes past 0xf00 and to 0 otherwise,
+    /// instead of leaving it unchanged
+    pub add_i_overflow: bool
+}
+
+/// Change of I after the registers are stored or loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndexIncrement {
+    /// I is left unchanged
+    Unchanged,
+    /// I = I + x
+    ByX,
+    /// I = I + x + 1, pointing after the last register
+    ByXPlusOne
+}
+
+impl Quirks {
+    /// The behaviour of this interpreter before the quirks could be
+    /// chosen, which most games written for modern interpreters expect.
+    pub const DEFAULT: Quirks = Quirks {
+        shift_vy: false,
+        load_store: IndexIncrement::Unchanged,
+        jump_vx: false,
+        clip_sprites: false,
+        logic_resets_vf: false,
+        display_wait: false,
+        add_i_overflow: true
+    };
+
+    /// The original COSMAC VIP interpreter.
+    pub const VIP: Quirks = Quirks {
+        shift_vy: true,
+        load_store: IndexIncrement::ByXPlusOne,
+        jump_vx: false,
+        clip_sprites: true,
+        logic_resets_vf: true,
+        display_wait: true,
+        add_i_overflow: false
+    };
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub const CHIP48: Quirks = Quirks {
+        shift_vy: false,
+        load_store: IndexIncrement::ByX,
+        jump_vx: true,
+        clip_sprites: true,
+        logic_resets_vf: false,
+        display_wait: false,
+        add_i_overflow: false
+    };
+
+    /// SUPER-CHIP 1.1.
+    pub const SCHIP: Quirks = Quirks {
+        shift_vy: false,
+        load_store: IndexIncrement::Unchanged,
+        jump_vx: true,
+        clip_sprites: true,
+        logic_resets_vf: false,
+        display_wait: false,
+        add_i_overflow: false
+    };
+
+    /// XO-CHIP, as implemented by Octo.
+    pub const XOCHIP: Quirks = Quirks {
+        shift_vy: true,
+        load_store: IndexIncrement::ByXPlusOne,
+        jump_vx: false,
+        clip_sprites: false,
+        logic_resets_vf: false,
+        display_wait: false,
+        add_i_overflow: false
+    };
+
+    /// Names accepted by `preset`.
+    pub const PRESETS: [&'static str; 5] = ["default", "vip", "chip48", "schip", "xochip"];
+
+    /// Quirks that `from_name` can turn on after the preset.
+    pub const OPTIONS: [&'static str; 2] = ["display-wait", "add-i-overflow"];
+
+    /// Find a preset by name.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "default" => Some(Quirks::DEFAULT),
+            "vip"     => Some(Quirks::VIP),
+            "chip48"  => Some(Quirks::CHIP48),
+            "schip"   => Some(Quirks::SCHIP),
+            "xochip"  => Some(Quirks::XOCHIP),
+            _ => None
+        }
+    }
+
+    /// A preset followed by quirks to turn on, separated by commas, e.g.
+    /// `default,display-wait`.
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        let mut names = name.split(',').map(str::trim);
+        let mut quirks = Quirks::preset(names.next()?)?;
+        for option in names {
+            match option.to_ascii_lowercase().as_str() {
+                "display-wait"   => quirks.display_wait = true,
+                "add-i-overflow" => quirks.add_i_overflow = true,
+                _ => return None
+            }
+        }
+
+        Some(quirks)
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_are_turned_on_after_the_preset() {
+        let quirks = Quirks::from_name("vip, add-i-overflow").unwrap();
+
+        assert!(quirks.add_i_overflow);
+        assert_eq!(Quirks { add_i_overflow: false, ..quirks }, Quirks::VIP);
+        assert_eq!(Quirks::from_name("default,display-wait"), Some(Quirks { display_wait: true, ..Quirks::DEFAULT }));
+        assert_eq!(Quirks::from_name("xochip"), Some(Quirks::XOCHIP));
+        assert_eq!(Quirks::from_name("vip,unknown"), None);
+    }
+}
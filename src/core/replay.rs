@@ -0,0 +1,279 @@
+use std::io;
+
+use crate::core::error::invalid_data;
+use crate::core::quirks::{IndexIncrement, Quirks};
+use crate::core::snapshot::{StateReader, StateWriter};
+use crate::core::Variant;
+
+// Input recording layout
+//
+// Offset  Size  Content
+// 0x00    4     Magic "C8RP"
+// 0x04    1     Format version
+// 0x05    8     Hash of the ROM
+// 0x0d    8     Seed of the random number generator
+// 0x15    4     Speed, in instructions per second
+// 0x19    1     Variant
+// 0x1a    7     Quirks: shift, load/store, jump, clip, logic, display
+//               wait, Fx1e overflow (5 before version 2, 6 before 3)
+// 0x21    ...   Runs of frames with the same keypad: keypad (2, one bit
+//               per key), number of frames (2). Big-endian.
+
+const REPLAY_MAGIC: &[u8; 4] = b"C8RP";
+const REPLAY_VERSION:     u8 = 3;
+
+/// Settings of the recorded session, everything needed to run it again
+/// the same way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayHeader {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub speed: u32,
+    pub variant: Variant,
+    pub quirks: Quirks
+}
+
+/// Hash identifying a ROM in a recording (64-bit FNV-1a).
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Records the keypad of every frame.
+pub struct InputRecorder {
+    header: ReplayHeader,
+    // Keypad and number of consecutive frames it was held
+    runs: Vec<(u16, u16)>
+}
+
+impl InputRecorder {
+    pub fn new(header: ReplayHeader) -> Self {
+        InputRecorder {
+            header,
+            runs: Vec::new()
+        }
+    }
+
+    /// Add the keypad of the next frame.
+    pub fn record(&mut self, keypad: [bool; 16]) {
+        let keys = pack(keypad);
+        match self.runs.last_mut() {
+            Some((last, count)) if *last == keys && *count < u16::MAX => *count += 1,
+            _ => self.runs.push((keys, 1))
+        }
+    }
+
+    /// Number of frames recorded.
+    pub fn frames(&self) -> usize {
+        self.runs.iter().map(|&(_, count)| count as usize).sum()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::with_header(REPLAY_MAGIC, REPLAY_VERSION);
+
+        writer.write_u64(self.header.rom_hash);
+        writer.write_u64(self.header.seed);
+        writer.write_u32(self.header.speed);
+        writer.write_u8(self.header.variant as u8);
+        let quirks = self.header.quirks;
+        writer.write_u8(quirks.shift_vy as u8);
+        writer.write_u8(quirks.load_store as u8);
+        writer.write_u8(quirks.jump_vx as u8);
+        writer.write_u8(quirks.clip_sprites as u8);
+        writer.write_u8(quirks.logic_resets_vf as u8);
+        writer.write_u8(quirks.display_wait as u8);
+        writer.write_u8(quirks.add_i_overflow as u8);
+
+        for &(keys, count) in self.runs.iter() {
+            writer.write_u16(keys);
+            writer.write_u16(count);
+        }
+
+        writer.finish()
+    }
+}
+
+/// Plays back a recording, one keypad per frame.
+pub struct InputReplay {
+    header: ReplayHeader,
+    runs: Vec<(u16, u16)>,
+    // Current run and frames already played from it
+    run: usize,
+    played: u16
+}
+
+impl InputReplay {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, io::Error> {
+        let mut reader = StateReader::with_header(data, REPLAY_MAGIC, REPLAY_VERSION, "input recording")?;
+
+        let rom_hash = reader.read_u64()?;
+        let seed = reader.read_u64()?;
+        let speed = reader.read_u32()?;
+        let variant = match reader.read_u8()? {
+            0 => Variant::Chip8,
+            1 => Variant::XoChip,
+            _ => return Err(invalid_data("unknown variant"))
+        };
+        let shift_vy = reader.read_u8()? != 0;
+        let load_store = match reader.read_u8()? {
+            0 => IndexIncrement::Unchanged,
+            1 => IndexIncrement::ByX,
+            2 => IndexIncrement::ByXPlusOne,
+            _ => return Err(invalid_data("unknown load/store quirk"))
+        };
+        let quirks = Quirks {
+            shift_vy,
+            load_store,
+            jump_vx: reader.read_u8()? != 0,
+            clip_sprites: reader.read_u8()? != 0,
+            logic_resets_vf: reader.read_u8()? != 0,
+            display_wait: reader.version() >= 2 && reader.read_u8()? != 0,
+            // Fx1e always set VF before version 3
+            add_i_overflow: reader.version() < 3 || reader.read_u8()? != 0
+        };
+
+        let mut runs = Vec::new();
+        while !reader.is_empty() {
+            runs.push((reader.read_u16()?, reader.read_u16()?));
+        }
+        reader.finish()?;
+
+        Ok(InputReplay {
+            header: ReplayHeader { rom_hash, seed, speed, variant, quirks },
+            runs,
+            run: 0,
+            played: 0
+        })
+    }
+
+    pub fn header(&self) -> &ReplayHeader {
+        &self.header
+    }
+
+    /// Keypad of the next frame, `None` once the recording is over.
+    pub fn next_frame(&mut self) -> Option<[bool; 16]> {
+        while let Some(&(keys, count)) = self.runs.get(self.run) {
+            if self.played < count {
+                self.played += 1;
+                return Some(unpack(keys));
+            }
+            self.run += 1;
+            self.played = 0;
+        }
+
+        None
+    }
+}
+
+fn pack(keypad: [bool; 16]) -> u16 {
+    keypad.iter().enumerate().fold(0, |keys, (key, &pressed)| keys | ((pressed as u16) << key))
+}
+
+fn unpack(keys: u16) -> [bool; 16] {
+    let mut keypad = [false; 16];
+    for (key, pressed) in keypad.iter_mut().enumerate() {
+        *pressed = keys & (1 << key) != 0;
+    }
+
+    keypad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: ReplayHeader = ReplayHeader {
+        rom_hash: 0x0123_4567_89ab_cdef,
+        seed: 42,
+        speed: 1000,
+        variant: Variant::XoChip,
+        quirks: Quirks::VIP
+    };
+
+    fn keypad(keys: &[usize]) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for &key in keys {
+            keypad[key] = true;
+        }
+
+        keypad
+    }
+
+    fn play(replay: &mut InputReplay) -> Vec<[bool; 16]> {
+        std::iter::from_fn(|| replay.next_frame()).collect()
+    }
+
+    #[test]
+    fn recording_reads_back() {
+        let frames = vec![keypad(&[]), keypad(&[0x5]), keypad(&[0x5]), keypad(&[0x0, 0xf]), keypad(&[])];
+        let mut recorder = InputRecorder::new(HEADER);
+        for &frame in frames.iter() {
+            recorder.record(frame);
+        }
+        assert_eq!(recorder.frames(), 5);
+
+        let mut replay = InputReplay::from_bytes(&recorder.to_bytes()).unwrap();
+        assert_eq!(*replay.header(), HEADER);
+        assert_eq!(play(&mut replay), frames);
+        assert_eq!(replay.next_frame(), None);
+    }
+
+    #[test]
+    fn long_runs_are_split() {
+        let mut recorder = InputRecorder::new(HEADER);
+        for _ in 0 .. u16::MAX as usize + 2 {
+            recorder.record(keypad(&[0x1]));
+        }
+        recorder.record(keypad(&[]));
+        assert_eq!(recorder.frames(), u16::MAX as usize + 3);
+
+        // Three runs of 4 bytes after the header
+        let bytes = recorder.to_bytes();
+        assert_eq!(bytes.len(), 0x21 + 3 * 4);
+
+        let frames = play(&mut InputReplay::from_bytes(&bytes).unwrap());
+        assert_eq!(frames.len(), u16::MAX as usize + 3);
+        assert!(frames[.. u16::MAX as usize + 2].iter().all(|&frame| frame == keypad(&[0x1])));
+        assert_eq!(frames.last(), Some(&keypad(&[])));
+    }
+
+    // A recording of the header in an older version, which had fewer
+    // quirk bytes
+    fn recording(version: u8) -> Vec<u8> {
+        let mut recorder = InputRecorder::new(HEADER);
+        recorder.record(keypad(&[0x2]));
+
+        let mut bytes = recorder.to_bytes();
+        bytes[4] = version;
+        match version {
+            1 => { bytes.drain(0x1f .. 0x21); },
+            2 => { bytes.remove(0x20); },
+            _ => {}
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn older_versions_are_read() {
+        for version in 1 ..= 2 {
+            let mut replay = InputReplay::from_bytes(&recording(version)).unwrap();
+            let quirks = replay.header().quirks;
+            assert_eq!(quirks.display_wait, version >= 2, "version {}", version);
+            assert!(quirks.add_i_overflow, "version {}", version);
+            assert_eq!(Quirks { display_wait: true, add_i_overflow: false, ..quirks }, Quirks::VIP);
+            assert_eq!(play(&mut replay), vec![keypad(&[0x2])]);
+        }
+    }
+
+    #[test]
+    fn unsupported_versions_are_rejected() {
+        for &version in [0, REPLAY_VERSION + 1].iter() {
+            let error = InputReplay::from_bytes(&recording(version)).err().unwrap();
+            assert_eq!(error.to_string(), format!("unsupported input recording version {}", version));
+        }
+
+        assert!(InputReplay::from_bytes(b"C8ST\x03").is_err());
+    }
+}
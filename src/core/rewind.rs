@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::io;
+
+use crate::core::Processor;
+use crate::core::TIMER_FREQUENCY;
+
+/// Frames between two snapshots, rewinding goes back in steps this long.
+pub const REWIND_INTERVAL: u32 = 4;
+
+/// Seconds of play kept by default.
+pub const DEFAULT_REWIND_SECONDS: u32 = 10;
+
+/// Difference between two snapshots: the XOR of their bytes, with the runs
+/// of zeros (the unchanged bytes) squeezed out.
+///
+/// Encoded as pairs of a number of unchanged bytes and a number of changed
+/// bytes, both u16 big-endian, each followed by the changed bytes.
+struct Delta {
+    data: Vec<u8>
+}
+
+impl Delta {
+    fn new(from: &[u8], to: &[u8]) -> Self {
+        let mut data = Vec::new();
+        let mut bytes = from.iter().zip(to.iter()).map(|(a, b)| a ^ b).peekable();
+
+        while bytes.peek().is_some() {
+            let mut unchanged: u16 = 0;
+            while unchanged < u16::MAX && bytes.peek() == Some(&0) {
+                bytes.next();
+                unchanged += 1;
+            }
+
+            let mut changed = Vec::new();
+            while changed.len() < u16::MAX as usize {
+                match bytes.peek() {
+                    Some(&byte) if byte != 0 => {
+                        changed.push(byte);
+                        bytes.next();
+                    },
+                    _ => break
+                }
+            }
+
+            data.extend_from_slice(&unchanged.to_be_bytes());
+            data.extend_from_slice(&(changed.len() as u16).to_be_bytes());
+            data.extend_from_slice(&changed);
+        }
+
+        Delta {
+            data
+        }
+    }
+
+    /// Turn one of the snapshots into the other.
+    fn apply(&self, snapshot: &[u8]) -> Vec<u8> {
+        let mut result = snapshot.to_vec();
+        let mut position = 0;
+        let mut data = self.data.iter();
+        let read_u16 = |data: &mut std::slice::Iter<u8>| {
+            let high = *data.next().unwrap_or(&0) as usize;
+            let low = *data.next().unwrap_or(&0) as usize;
+            (high << 8) | low
+        };
+
+        while data.len() > 0 {
+            position += read_u16(&mut data);
+            for _ in 0 .. read_u16(&mut data) {
+                result[position] ^= data.next().unwrap_or(&0);
+                position += 1;
+            }
+        }
+
+        result
+    }
+
+    fn size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+/// Snapshots of the last seconds of play, to step backwards through them.
+///
+/// Only the newest snapshot is kept whole, each older one is stored as its
+/// difference with the next, which is small as a frame changes few bytes
+/// of the memory and the screen.
+pub struct Rewind {
+    latest: Option<Vec<u8>>,
+    // Differences to older snapshots, the oldest first
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+    // Frames since the last snapshot or step back
+    frames: u32
+}
+
+impl Rewind {
+    /// # Arguments
+    ///
+    /// * `seconds` - How far back the game can be rewound
+    pub fn new(seconds: u32) -> Self {
+        Rewind {
+            latest: None,
+            deltas: VecDeque::new(),
+            capacity: (seconds.saturating_mul(TIMER_FREQUENCY) / REWIND_INTERVAL) as usize,
+            frames: 0
+        }
+    }
+
+    /// Call once per frame played, takes a snapshot every
+    /// `REWIND_INTERVAL` frames.
+    pub fn frame(&mut self, processor: &Processor) {
+        self.frames += 1;
+        if self.frames < REWIND_INTERVAL && self.latest.is_some() {
+            return;
+        }
+        self.frames = 0;
+
+        let snapshot = processor.save_state();
+        if let Some(latest) = self.latest.take() {
+            if latest.len() == snapshot.len() {
+                self.deltas.push_back(Delta::new(&snapshot, &latest));
+            } else {
+                // The machine changed too much, e.g. a state of another
+                // variant was loaded: the older snapshots can't be rebuilt
+                self.deltas.clear();
+            }
+        }
+        self.latest = Some(snapshot);
+
+        while self.deltas.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Call once per frame while rewinding, steps back every
+    /// `REWIND_INTERVAL` frames.
+    ///
+    /// Returns false once the oldest snapshot is reached.
+    pub fn rewind_frame(&mut self, processor: &mut Processor) -> Result<bool, io::Error> {
+        self.frames += 1;
+        if self.frames < REWIND_INTERVAL {
+            return Ok(true);
+        }
+        self.frames = 0;
+
+        self.step_back(processor)
+    }
+
+    /// Restore the newest snapshot and drop it, the next step goes further
+    /// back.
+    ///
+    /// Returns false once the oldest snapshot is reached.
+    pub fn step_back(&mut self, processor: &mut Processor) -> Result<bool, io::Error> {
+        let latest = match self.latest.as_ref() {
+            Some(latest) => latest,
+            None => return Ok(false)
+        };
+        processor.load_state(latest)?;
+
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                self.latest = Some(delta.apply(latest));
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
+
+    /// Seconds of play that can be rewound.
+    pub fn seconds(&self) -> f32 {
+        (self.deltas.len() as u32 * REWIND_INTERVAL) as f32 / TIMER_FREQUENCY as f32
+    }
+
+    /// Memory used by the snapshots, in bytes.
+    pub fn size(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(Delta::size).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Quirks, Variant};
+
+    #[test]
+    fn stepping_back_restores_every_snapshot() {
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+        // ADD V0, 1 / LD [I], V0 / JP 200
+        processor.load(&[0x70, 0x01, 0xf0, 0x55, 0x12, 0x00]).unwrap();
+        processor.set_i(0x300);
+
+        let mut rewind = Rewind::new(DEFAULT_REWIND_SECONDS);
+        let mut snapshots = Vec::new();
+        for frame in 0 .. 8 * REWIND_INTERVAL {
+            for _ in 0 .. 7 {
+                processor.tick([false; 16]).unwrap();
+            }
+            rewind.frame(&processor);
+            if frame % REWIND_INTERVAL == 0 {
+                snapshots.push(processor.save_state());
+            }
+        }
+
+        while let Some(snapshot) = snapshots.pop() {
+            let more = rewind.step_back(&mut processor).unwrap();
+            assert_eq!(processor.save_state(), snapshot);
+            assert_eq!(more, !snapshots.is_empty());
+        }
+    }
+
+    #[test]
+    fn capacity_does_not_overflow() {
+        assert_eq!(Rewind::new(u32::MAX).capacity, (u32::MAX / REWIND_INTERVAL) as usize);
+    }
+}
@@ -0,0 +1,91 @@
+/// Random number generator of the CXKK instruction.
+///
+/// A xorshift64* generator: small, fast and fully determined by its seed,
+/// so that runs can be reproduced and the state saved with the machine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    /// Create a generator from a seed. Any seed is valid, including 0.
+    pub fn new(seed: u64) -> Self {
+        // The state of xorshift must not be 0, mix the seed so that close
+        // seeds give unrelated sequences
+        Rng {
+            state: splitmix64(seed) | 1
+        }
+    }
+
+    /// Next random byte, between 0 and 255.
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Internal state, to be saved with the machine.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Restore a state returned by `state`.
+    pub fn from_state(state: u64) -> Option<Self> {
+        if state == 0 {
+            return None;
+        }
+
+        Some(Rng {
+            state
+        })
+    }
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Processor, Quirks, Variant};
+
+    #[test]
+    fn cxkk_can_give_ff() {
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+        processor.set_seed(0);
+        // RND V0, FF / JP 200
+        processor.load(&[0xc0, 0xff, 0x12, 0x00]).unwrap();
+
+        let mut found = false;
+        for _ in 0 .. 10_000 {
+            processor.tick([false; 16]).unwrap();
+            found |= processor.v(0x0) == 0xff;
+            processor.tick([false; 16]).unwrap();
+        }
+
+        assert!(found);
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_sequence() {
+        let sequence = |seed| {
+            let mut rng = Rng::new(seed);
+            (0 .. 64).map(|_| rng.next_u8()).collect::<Vec<u8>>()
+        };
+
+        assert_eq!(sequence(42), sequence(42));
+        assert_ne!(sequence(42), sequence(43));
+        assert_eq!(sequence(0), sequence(0));
+    }
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+/// Frequency of the delay and sound timers, and of the display.
+pub const TIMER_FREQUENCY:  u32 = 60;
+/// Instructions executed per second unless told otherwise.
+pub const DEFAULT_SPEED:    u32 = 600;
+
+// Frames run at once after a stall, the rest of the lag is dropped so that
+// the game does not fast-forward
+const MAX_FRAMES_BEHIND:    u32 = 5;
+
+/// Splits the time elapsed on the host into 60 Hz frames.
+///
+/// Each frame runs speed / 60 instructions then decrements the timers
+/// once, so the game speed does not depend on the host.
+pub struct Scheduler {
+    speed: u32,
+    // Instructions owed by previous frames when the speed is not a
+    // multiple of 60, in 1/60th of an instruction
+    remainder: u32,
+    frame_duration: Duration,
+    // Elapsed time not yet turned into frames
+    lag: Duration
+}
+
+impl Scheduler {
+    /// Create a scheduler
+    ///
+    /// # Arguments
+    ///
+    /// * `speed` - Instructions executed per second, e.g. 500 to 1000
+    pub fn new(speed: u32) -> Self {
+        Scheduler {
+            speed: speed.max(1),
+            remainder: 0,
+            frame_duration: Duration::from_secs(1) / TIMER_FREQUENCY,
+            lag: Duration::from_secs(0)
+        }
+    }
+
+    /// Instructions to execute in the next frame, before the timers are
+    /// decremented.
+    pub fn instructions_per_frame(&mut self) -> u32 {
+        self.remainder += self.speed;
+        let instructions = self.remainder / TIMER_FREQUENCY;
+        self.remainder %= TIMER_FREQUENCY;
+
+        instructions
+    }
+
+    /// Account for the time elapsed since the last call and return the
+    /// number of frames to run.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.lag += elapsed;
+
+        let mut frames = 0;
+        while self.lag >= self.frame_duration {
+            self.lag -= self.frame_duration;
+            frames += 1;
+        }
+
+        if frames > MAX_FRAMES_BEHIND {
+            frames = MAX_FRAMES_BEHIND;
+        }
+
+        frames
+    }
+
+    /// Time left before the next frame is due.
+    pub fn time_to_next_frame(&self) -> Duration {
+        self.frame_duration - self.lag
+    }
+
+    /// Start over, e.g. when the game is reset: forget the elapsed time
+    /// and the instructions owed by previous frames.
+    pub fn reset(&mut self) {
+        self.remainder = 0;
+        self.lag = Duration::from_secs(0);
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_SPEED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fractional_instructions_add_up() {
+        // 100 instructions per second is 1 2/3 per frame
+        let mut scheduler = Scheduler::new(100);
+        let frames: Vec<u32> = (0 .. 6).map(|_| scheduler.instructions_per_frame()).collect();
+        assert_eq!(frames, vec![1, 2, 2, 1, 2, 2]);
+        assert_eq!(frames.iter().sum::<u32>(), 10);
+
+        scheduler.instructions_per_frame();
+        scheduler.reset();
+        assert_eq!(scheduler.instructions_per_frame(), 1);
+    }
+
+    #[test]
+    fn advance_turns_time_into_frames() {
+        let mut scheduler = Scheduler::default();
+        let frame = Duration::from_secs(1) / TIMER_FREQUENCY;
+
+        assert_eq!(scheduler.advance(frame / 2), 0);
+        assert_eq!(scheduler.advance(frame / 2), 1);
+        assert_eq!(scheduler.advance(frame * 3 + frame / 4), 3);
+        assert_eq!(scheduler.time_to_next_frame(), frame - frame / 4);
+    }
+
+    #[test]
+    fn advance_drops_the_lag_past_the_cap() {
+        let mut scheduler = Scheduler::default();
+        let frame = Duration::from_secs(1) / TIMER_FREQUENCY;
+
+        // Two seconds behind, only the capped frames are run and the game
+        // does not fast-forward afterwards
+        assert_eq!(scheduler.advance(Duration::from_secs(2)), MAX_FRAMES_BEHIND);
+        assert_eq!(scheduler.advance(Duration::from_secs(0)), 0);
+        assert_eq!(scheduler.advance(frame), 1);
+    }
+}
@@ -0,0 +1,155 @@
+use std::io;
+
+use crate::core::error::invalid_data;
+
+// Save state layout
+//
+// Offset  Size  Content
+// 0x00    4     Magic "C8ST"
+// 0x04    1     Format version
+// 0x05    ...   Processor fields, in declaration order. Multi-byte values
+//               are stored big-endian, like the opcodes in memory.
+
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+
+/// Current version of the save state format.
+pub const STATE_VERSION: u8 = 4;
+
+/// Serializes the fields of a snapshot into a byte buffer.
+pub struct StateWriter {
+    buffer: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::with_header(STATE_MAGIC, STATE_VERSION)
+    }
+
+    /// Writer for another file format sharing the layout of the save
+    /// states: a magic, a version, then big-endian fields.
+    pub fn with_header(magic: &[u8; 4], version: u8) -> Self {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(magic);
+        buffer.push(version);
+
+        StateWriter {
+            buffer
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// Reads back the fields written by a `StateWriter`.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    version: u8,
+    // Name of the format in the error messages
+    name: &'static str
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the header of the snapshot and positions the reader
+    /// at the first field.
+    pub fn new(data: &'a [u8]) -> Result<Self, io::Error> {
+        Self::with_header(data, STATE_MAGIC, STATE_VERSION, "save state")
+    }
+
+    /// Reader for a format written with `StateWriter::with_header`,
+    /// accepting versions up to `version`.
+    pub fn with_header(data: &'a [u8], magic: &[u8; 4], version: u8, name: &'static str) -> Result<Self, io::Error> {
+        let header_size = magic.len() + 1;
+        if data.len() < header_size || &data[.. magic.len()] != magic {
+            return Err(invalid_data(&format!("not a {}", name)));
+        }
+
+        let data_version = data[magic.len()];
+        if data_version == 0 || data_version > version {
+            return Err(invalid_data(&format!("unsupported {} version {}", name, data_version)));
+        }
+
+        Ok(StateReader {
+            data,
+            position: header_size,
+            version: data_version,
+            name
+        })
+    }
+
+    /// Version of the format the snapshot was written with.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, io::Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, io::Error> {
+        let bytes = self.read_bytes(2)?;
+
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, io::Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, io::Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], io::Error> {
+        if self.position + len > self.data.len() {
+            return Err(invalid_data(&format!("{} is truncated", self.name)));
+        }
+
+        let bytes = &self.data[self.position .. self.position + len];
+        self.position += len;
+
+        Ok(bytes)
+    }
+
+    /// Whether every field has been read.
+    pub fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    /// Fails if there is data left after the last field.
+    pub fn finish(self) -> Result<(), io::Error> {
+        if self.position != self.data.len() {
+            return Err(invalid_data(&format!("unexpected data at the end of the {}", self.name)));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io;
+use std::io::Write;
+
+use crate::core::mnemonic_at;
+use crate::core::Chip8Error;
+use crate::core::Output;
+use crate::core::Processor;
+
+// Binary trace layout
+//
+// Offset  Size  Content
+// 0x00    4     Magic "C8TR"
+// 0x04    1     Format version
+// 0x05    ...   Records of RECORD_SIZE bytes, big-endian:
+//               cycle (8), pc (2), opcode (2), next word (2),
+//               then the state before and after the instruction:
+//               V0-VF (16), I (2), SP (1), DT (1), ST (1)
+
+const TRACE_MAGIC: &[u8; 4] = b"C8TR";
+const TRACE_VERSION:     u8 = 1;
+const STATE_SIZE:     usize = 16 + 2 + 1 + 1 + 1;
+
+/// Size of a record in a binary trace.
+pub const RECORD_SIZE: usize = 8 + 2 + 2 + 2 + 2 * STATE_SIZE;
+
+/// Registers, index and timers at one point of the execution.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpuState {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8
+}
+
+impl CpuState {
+    pub fn capture(processor: &Processor) -> Self {
+        let mut v = [0; 16];
+        for (x, register) in v.iter_mut().enumerate() {
+            *register = processor.v(x as u8);
+        }
+
+        CpuState {
+            v,
+            i: processor.i(),
+            sp: processor.sp() as u8,
+            delay_timer: processor.delay_timer(),
+            sound_timer: processor.sound_timer()
+        }
+    }
+
+    fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.v);
+        buffer.extend_from_slice(&self.i.to_be_bytes());
+        buffer.push(self.sp);
+        buffer.push(self.delay_timer);
+        buffer.push(self.sound_timer);
+    }
+}
+
+/// One executed instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceRecord {
+    /// Instructions executed before this one since tracing was created
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    /// Word following the opcode, the address of the XO-CHIP `F000 nnnn`
+    pub next: u16,
+    pub before: CpuState,
+    pub after: CpuState
+}
+
+impl TraceRecord {
+    /// Mnemonic of the instruction.
+    pub fn disassembly(&self) -> String {
+        let bytes = [(self.opcode >> 8) as u8, self.opcode as u8, (self.next >> 8) as u8, self.next as u8];
+        mnemonic_at(&bytes, 0).unwrap_or_else(|| "???".to_string())
+    }
+
+    /// Text form: cycle, address, opcode, mnemonic, then what the
+    /// instruction changed.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{:>10}  {:03X}  {:04X}  {:<20}", self.cycle, self.pc, self.opcode, self.disassembly());
+
+        let (before, after) = (&self.before, &self.after);
+        for x in 0 .. 16 {
+            if before.v[x] != after.v[x] {
+                let _ = write!(text, "  V{:X}: {:02x} -> {:02x}", x, before.v[x], after.v[x]);
+            }
+        }
+        if before.i != after.i {
+            let _ = write!(text, "  I: {:#05x} -> {:#05x}", before.i, after.i);
+        }
+        if before.sp != after.sp {
+            let _ = write!(text, "  SP: {} -> {}", before.sp, after.sp);
+        }
+        if before.delay_timer != after.delay_timer {
+            let _ = write!(text, "  DT: {} -> {}", before.delay_timer, after.delay_timer);
+        }
+        if before.sound_timer != after.sound_timer {
+            let _ = write!(text, "  ST: {} -> {}", before.sound_timer, after.sound_timer);
+        }
+
+        text.trim_end().to_string()
+    }
+
+    /// Binary form, `RECORD_SIZE` bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(RECORD_SIZE);
+        buffer.extend_from_slice(&self.cycle.to_be_bytes());
+        buffer.extend_from_slice(&self.pc.to_be_bytes());
+        buffer.extend_from_slice(&self.opcode.to_be_bytes());
+        buffer.extend_from_slice(&self.next.to_be_bytes());
+        self.before.write(&mut buffer);
+        self.after.write(&mut buffer);
+
+        buffer
+    }
+}
+
+/// Destination of the trace records.
+pub trait TraceSink {
+    fn record(&mut self, record: &TraceRecord) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes one line of text per instruction.
+pub struct TextTrace<W: Write> {
+    output: W
+}
+
+impl<W: Write> TextTrace<W> {
+    pub fn new(output: W) -> Self {
+        TextTrace {
+            output
+        }
+    }
+}
+
+impl<W: Write> TraceSink for TextTrace<W> {
+    fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        writeln!(self.output, "{}", record.to_text())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Writes fixed-size binary records, see the layout above.
+pub struct BinaryTrace<W: Write> {
+    output: W
+}
+
+impl<W: Write> BinaryTrace<W> {
+    /// Create the sink and write the header.
+    pub fn new(mut output: W) -> io::Result<Self> {
+        output.write_all(TRACE_MAGIC)?;
+        output.write_all(&[TRACE_VERSION])?;
+
+        Ok(BinaryTrace {
+            output
+        })
+    }
+}
+
+impl<W: Write> TraceSink for BinaryTrace<W> {
+    fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        self.output.write_all(&record.to_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Keeps the last instructions in memory, e.g. to show what led to a fault.
+pub struct RingTrace {
+    records: VecDeque<TraceRecord>,
+    capacity: usize
+}
+
+impl RingTrace {
+    pub fn new(capacity: usize) -> Self {
+        RingTrace {
+            records: VecDeque::with_capacity(capacity),
+            capacity
+        }
+    }
+
+    /// Recorded instructions, oldest first.
+    pub fn records(&self) -> impl Iterator<Item = &TraceRecord> {
+        self.records.iter()
+    }
+
+    /// Text form of the recorded instructions.
+    pub fn dump(&self) -> String {
+        let lines: Vec<String> = self.records().map(TraceRecord::to_text).collect();
+        lines.join("\n")
+    }
+}
+
+impl TraceSink for RingTrace {
+    fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record.clone());
+
+        Ok(())
+    }
+}
+
+/// Runs the processor and sends a record of every instruction to the
+/// sinks while it is enabled.
+#[derive(Default)]
+pub struct Tracer {
+    enabled: bool,
+    cycle: u64,
+    ring: Option<RingTrace>,
+    sinks: Vec<Box<dyn TraceSink>>,
+    // Failure of a sink, which disabled the tracer
+    error: Option<io::Error>
+}
+
+impl Tracer {
+    /// Create a disabled tracer without any sink.
+    pub fn new() -> Self {
+        Tracer {
+            enabled: false,
+            cycle: 0,
+            ring: None,
+            sinks: Vec::new(),
+            error: None
+        }
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn TraceSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Keep the last `capacity` instructions in memory.
+    pub fn set_ring(&mut self, capacity: usize) {
+        self.ring = Some(RingTrace::new(capacity));
+    }
+
+    pub fn ring(&self) -> Option<&RingTrace> {
+        self.ring.as_ref()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Start or stop recording. Disabling flushes the sinks.
+    pub fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+        self.enabled = enabled;
+        if !enabled {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Instructions executed so far.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Error of the sink that disabled the tracer, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Execute one instruction, recording it if the tracer is enabled.
+    ///
+    /// Ticks where the processor waits are not recorded, a faulty
+    /// instruction is. A failing sink disables the tracer, see `take_error`.
+    pub fn tick(&mut self, processor: &mut Processor, keypad: [bool; 16]) -> Result<Output, Chip8Error> {
+        if !self.enabled {
+            let output = processor.tick(keypad)?;
+            self.cycle += output.executed as u64;
+            return Ok(output);
+        }
+
+        let pc = processor.pc();
+        let memory = processor.memory();
+        let word = |address: usize| {
+            ((*memory.get(address).unwrap_or(&0) as u16) << 8) | *memory.get(address + 1).unwrap_or(&0) as u16
+        };
+        let opcode = word(pc as usize);
+        let next = word(pc as usize + 2);
+        let before = CpuState::capture(processor);
+
+        let result = processor.tick(keypad);
+        if result.as_ref().is_ok_and(|output| !output.executed) {
+            return result;
+        }
+
+        let record = TraceRecord {
+            cycle: self.cycle,
+            pc,
+            opcode,
+            next,
+            before,
+            after: CpuState::capture(processor)
+        };
+        self.cycle += 1;
+
+        if let Some(ring) = self.ring.as_mut() {
+            let _ = ring.record(&record);
+        }
+        if let Err(e) = self.sinks.iter_mut().try_for_each(|sink| sink.record(&record)) {
+            self.enabled = false;
+            self.error = Some(e);
+        }
+
+        result
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Quirks, Variant};
+
+    fn traced(program: &[u8]) -> (Tracer, Processor) {
+        let mut processor = Processor::new(Variant::Chip8, Quirks::default());
+        processor.load(program).unwrap();
+        let mut tracer = Tracer::new();
+        tracer.set_ring(16);
+        tracer.set_enabled(true).unwrap();
+
+        (tracer, processor)
+    }
+
+    #[test]
+    fn waiting_for_a_key_is_not_traced() {
+        // LD V0, K
+        let (mut tracer, mut processor) = traced(&[0xf0, 0x0a]);
+        for _ in 0 .. 5 {
+            tracer.tick(&mut processor, [false; 16]).unwrap();
+        }
+
+        assert_eq!(tracer.cycle(), 1);
+        let opcodes: Vec<u16> = tracer.ring().unwrap().records().map(|record| record.opcode).collect();
+        assert_eq!(opcodes, vec![0xf00a]);
+    }
+
+    #[test]
+    fn faulty_instruction_is_traced() {
+        // LD V0, 1 / RET outside of a subroutine
+        let (mut tracer, mut processor) = traced(&[0x60, 0x01, 0x00, 0xee]);
+        tracer.tick(&mut processor, [false; 16]).unwrap();
+        assert!(tracer.tick(&mut processor, [false; 16]).is_err());
+
+        let last = tracer.ring().unwrap().records().last().unwrap();
+        assert_eq!((last.pc, last.opcode), (0x202, 0x00ee));
+    }
+}
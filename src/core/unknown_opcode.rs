@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// What the processor does with an opcode it does not know.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnknownOpcodePolicy {
+    /// Stop with `Chip8Error::InvalidOpcode`
+    #[default]
+    Halt,
+    /// Skip the instruction
+    Skip,
+    /// Treat `0nnn` as a call to machine code that does nothing, halt on
+    /// any other unknown opcode
+    IgnoreSys
+}
+
+impl UnknownOpcodePolicy {
+    /// Names accepted by `from_name`.
+    pub const NAMES: [&'static str; 3] = ["halt", "skip", "sys"];
+
+    pub fn from_name(name: &str) -> Option<UnknownOpcodePolicy> {
+        match name.to_ascii_lowercase().as_str() {
+            "halt" => Some(UnknownOpcodePolicy::Halt),
+            "skip" => Some(UnknownOpcodePolicy::Skip),
+            "sys"  => Some(UnknownOpcodePolicy::IgnoreSys),
+            _ => None
+        }
+    }
+}
+
+/// Unknown opcodes met during a run, with the addresses where they were
+/// executed.
+///
+/// A ROM hitting many of them probably needs another variant.
+#[derive(Clone, Debug, Default)]
+pub struct UnknownOpcodes {
+    hits: BTreeMap<u16, BTreeSet<u16>>
+}
+
+impl UnknownOpcodes {
+    pub fn new() -> Self {
+        UnknownOpcodes {
+            hits: BTreeMap::new()
+        }
+    }
+
+    /// Record an opcode executed at an address. Returns true the first
+    /// time this address is seen for this opcode.
+    pub fn record(&mut self, opcode: u16, address: u16) -> bool {
+        self.hits.entry(opcode).or_default().insert(address)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hits.is_empty()
+    }
+
+    /// Unknown opcodes and the addresses where they were hit.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &BTreeSet<u16>)> {
+        self.hits.iter().map(|(&opcode, addresses)| (opcode, addresses))
+    }
+}
+
+impl fmt::Display for UnknownOpcodes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (opcode, addresses) in self.iter() {
+            let addresses: Vec<String> = addresses.iter().map(|address| format!("{:#05x}", address)).collect();
+            writeln!(f, "{:04x} at {}", opcode, addresses.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_names() {
+        let policies: Vec<_> = UnknownOpcodePolicy::NAMES.iter().map(|name| UnknownOpcodePolicy::from_name(name)).collect();
+        assert_eq!(policies, vec![
+            Some(UnknownOpcodePolicy::Halt),
+            Some(UnknownOpcodePolicy::Skip),
+            Some(UnknownOpcodePolicy::IgnoreSys)
+        ]);
+        assert_eq!(UnknownOpcodePolicy::from_name("SKIP"), Some(UnknownOpcodePolicy::Skip));
+        assert_eq!(UnknownOpcodePolicy::from_name("ignore"), None);
+    }
+
+    #[test]
+    fn report_counts_each_address_once() {
+        let mut opcodes = UnknownOpcodes::new();
+        assert!(opcodes.is_empty());
+
+        assert!(opcodes.record(0x5f01, 0x204));
+        assert!(opcodes.record(0x5f01, 0x200));
+        assert!(!opcodes.record(0x5f01, 0x204));
+        assert!(opcodes.record(0x0123, 0x202));
+
+        assert!(!opcodes.is_empty());
+        assert_eq!(opcodes.to_string(), "0123 at 0x202\n5f01 at 0x200, 0x204\n");
+    }
+}
@@ -0,0 +1,854 @@
+use std::{fs, io, thread};
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Instant;
+use std::io::prelude::*;
+use std::sync::mpsc;
+use colored::*;
+
+use chip8_emulator::*;
+
+/// Config read when `--config` is not given.
+const CONFIG_PATH: &str = "chip8.toml";
+
+/// Run a game in the SDL2 window, fails if it can't be started or faults.
+///
+/// # Arguments
+///
+/// * `args` - The game followed by the options
+pub fn run(args: &[String]) -> ExitCode {
+    // Read the game name and the options
+    let mut debug = false;
+    let mut xochip = false;
+    let mut quirks = None;
+    let mut speed = DEFAULT_SPEED;
+    let mut unknown_opcode_policy = UnknownOpcodePolicy::default();
+    let mut seed = None;
+    let mut tracer = Tracer::new();
+    let mut trace = false;
+    let mut audio = true;
+    let mut audio_settings = AudioSettings::default();
+    let mut display_settings = DisplaySettings::default();
+    let mut filter = FrameFilter::new(Filter::default());
+    let mut screenshot_scale = ScreenshotScale::default();
+    let mut capture_path = None;
+    let mut capture_format = None;
+    let mut capture_scale = DEFAULT_CAPTURE_SCALE;
+    let mut rewind_seconds = DEFAULT_REWIND_SECONDS;
+    let mut config_path = None;
+    let mut palette = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut game = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            "--xochip" => xochip = true,
+            "--quirks" => {
+                let name = args.next().map(String::as_str).unwrap_or("");
+                match Quirks::from_name(name) {
+                    Some(value) => quirks = Some(value),
+                    None => {
+                        println!("{}: unknown quirks `{}`, expected one of {} then any of {}, separated by commas",
+                                 "Error".red(), name, Quirks::PRESETS.join(", "), Quirks::OPTIONS.join(", "));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--speed" => {
+                match args.next().and_then(|value| value.parse().ok()).filter(|&speed| speed > 0) {
+                    Some(value) => speed = value,
+                    None => {
+                        println!("{}: --speed expects a number of instructions per second", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--unknown" => {
+                let name = args.next().map(String::as_str).unwrap_or("");
+                match UnknownOpcodePolicy::from_name(name) {
+                    Some(policy) => unknown_opcode_policy = policy,
+                    None => {
+                        println!("{}: unknown policy `{}`, expected one of {}",
+                                 "Error".red(), name, UnknownOpcodePolicy::NAMES.join(", "));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--seed" => {
+                match args.next().and_then(|value| value.parse().ok()) {
+                    Some(value) => seed = Some(value),
+                    None => {
+                        println!("{}: --seed expects a number", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--trace" | "--trace-bin" => {
+                let path = args.next().map(String::as_str).unwrap_or("");
+                let sink = fs::File::create(path).and_then(|file| {
+                    let file = io::BufWriter::new(file);
+                    if arg == "--trace" {
+                        Ok(Box::new(TextTrace::new(file)) as Box<dyn TraceSink>)
+                    } else {
+                        Ok(Box::new(BinaryTrace::new(file)?) as Box<dyn TraceSink>)
+                    }
+                });
+                match sink {
+                    Ok(sink) => {
+                        tracer.add_sink(sink);
+                        trace = true;
+                    },
+                    Err(e) => {
+                        println!("{}: can't create the trace `{}`: {}", "Error".red(), path, e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--trace-ring" => {
+                match args.next().and_then(|value| value.parse().ok()) {
+                    Some(capacity) => {
+                        tracer.set_ring(capacity);
+                        trace = true;
+                    },
+                    None => {
+                        println!("{}: --trace-ring expects a number of instructions", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--scale" => {
+                match args.next().and_then(|value| value.parse().ok()).filter(|&scale| scale > 0) {
+                    Some(scale) => display_settings.scale = scale,
+                    None => {
+                        println!("{}: --scale expects the size of a pixel", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--scaling" => {
+                let name = args.next().map(String::as_str).unwrap_or("");
+                match ScaleMode::from_name(name) {
+                    Some(mode) => display_settings.scale_mode = mode,
+                    None => {
+                        println!("{}: unknown scaling `{}`, expected one of {}",
+                                 "Error".red(), name, ScaleMode::NAMES.join(", "));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--fullscreen" => display_settings.fullscreen = true,
+            "--filter" => {
+                let name = args.next().map(String::as_str).unwrap_or("");
+                match Filter::from_name(name) {
+                    Some(mode) => filter = FrameFilter::new(mode),
+                    None => {
+                        println!("{}: unknown filter `{}`, expected one of {}, blend:<frames> or phosphor:<decay>",
+                                 "Error".red(), name, Filter::NAMES.join(", "));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--screenshot-scale" => {
+                let name = args.next().map(String::as_str).unwrap_or("");
+                match ScreenshotScale::from_name(name) {
+                    Some(scale) => screenshot_scale = scale,
+                    None => {
+                        println!("{}: unknown screenshot scale `{}`, expected one of {} or a number",
+                                 "Error".red(), name, ScreenshotScale::NAMES.join(", "));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--capture" => {
+                let path = args.next().map(String::as_str).unwrap_or("");
+                match CaptureFormat::from_path(path) {
+                    Some(format) => capture_path = Some((path, format)),
+                    None => {
+                        println!("{}: --capture expects a file ending in .{}",
+                                 "Error".red(), CaptureFormat::NAMES.join(" or ."));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--capture-format" => {
+                let name = args.next().map(String::as_str).unwrap_or("");
+                match CaptureFormat::from_name(name) {
+                    Some(format) => capture_format = Some(format),
+                    None => {
+                        println!("{}: unknown capture format `{}`, expected one of {}",
+                                 "Error".red(), name, CaptureFormat::NAMES.join(", "));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--capture-scale" => {
+                match args.next().and_then(|value| value.parse().ok()).filter(|&scale| scale > 0) {
+                    Some(scale) => capture_scale = scale,
+                    None => {
+                        println!("{}: --capture-scale expects the size of a pixel", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--no-audio" => audio = false,
+            "--frequency" => {
+                match args.next().and_then(|value| value.parse().ok()).filter(|&frequency: &f32| frequency > 0.0) {
+                    Some(frequency) => audio_settings.frequency = frequency,
+                    None => {
+                        println!("{}: --frequency expects a frequency in Hz", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--volume" => {
+                match args.next().and_then(|value| value.parse().ok()).filter(|&volume: &u32| volume <= 100) {
+                    Some(volume) => audio_settings.volume = volume as f32 / 100.0,
+                    None => {
+                        println!("{}: --volume expects a percentage", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--waveform" => {
+                let name = args.next().map(String::as_str).unwrap_or("");
+                match Waveform::from_name(name) {
+                    Some(waveform) => audio_settings.waveform = waveform,
+                    None => {
+                        println!("{}: unknown waveform `{}`, expected one of {}",
+                                 "Error".red(), name, Waveform::NAMES.join(", "));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--rewind" => {
+                match args.next().and_then(|value| value.parse().ok()) {
+                    Some(seconds) => rewind_seconds = seconds,
+                    None => {
+                        println!("{}: --rewind expects a number of seconds", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--config" => {
+                match args.next() {
+                    Some(path) => config_path = Some(path.as_str()),
+                    None => {
+                        println!("{}: --config expects a file", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--palette" => {
+                match args.next() {
+                    Some(name) => palette = Some(name.as_str()),
+                    None => {
+                        println!("{}: --palette expects the name of a palette", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--record" => {
+                match args.next() {
+                    Some(path) => record = Some(path),
+                    None => {
+                        println!("{}: --record expects a file", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--replay" => {
+                let path = args.next().map(String::as_str).unwrap_or("");
+                match fs::read(path).and_then(|data| InputReplay::from_bytes(&data)) {
+                    Ok(recording) => replay = Some(recording),
+                    Err(e) => {
+                        println!("{}: can't read the recording `{}`: {}", "Error".red(), path, e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            _ => game = Some(arg)
+        }
+    }
+
+    // A replay runs with the settings it was recorded with
+    if let Some(header) = replay.as_ref().map(InputReplay::header) {
+        xochip = header.variant == Variant::XoChip;
+        quirks = Some(header.quirks);
+        speed = header.speed;
+        seed = Some(header.seed);
+    }
+
+    let game = match game {
+        Some(game) => game,
+        None => {
+            println!("{}: no game specified", "Error".red());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Key bindings and palettes come from the config, the section of the
+    // game in it is found by its file name
+    let config = match read_config(config_path) {
+        Some(config) => config,
+        None => return ExitCode::FAILURE
+    };
+    let config_path = config_path.unwrap_or(CONFIG_PATH);
+    let rom_name = Path::new(game).file_name().and_then(|name| name.to_str());
+    let keymap = match Keymap::from_config(&config, rom_name) {
+        Ok(keymap) => keymap,
+        Err(e) => {
+            println!("{}: can't read the key bindings in {}: {}", "Error".red(), config_path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut palettes = match Palettes::from_config(&config, rom_name) {
+        Ok(palettes) => palettes,
+        Err(e) => {
+            println!("{}: can't read the palettes in {}: {}", "Error".red(), config_path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(name) = palette {
+        if !palettes.select(name) {
+            println!("{}: unknown palette `{}`, expected one of {}",
+                     "Error".red(), name, palettes.names().join(", "));
+            return ExitCode::FAILURE;
+        }
+    }
+
+    println!("{} Chip-8 emulator", "Initializing".green());
+
+    println!("{} SDL2", "Initializing".green());
+    let sdl_context = match sdl2::init() {
+        Ok(sdl_context) => sdl_context,
+        Err(e) => {
+            println!("{}: can't initialize SDL2: {}", "Error".red(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Create the VM, XO-CHIP games are recognized by their extension
+    let variant = if xochip || game.ends_with(".xo8") {
+        Variant::XoChip
+    } else {
+        Variant::Chip8
+    };
+
+    // The quirks come from the command line, then from a file next to the
+    // ROM, then from the instruction set
+    let quirks = quirks.or_else(|| crate::read_quirks(game)).unwrap_or(match variant {
+        Variant::Chip8  => Quirks::DEFAULT,
+        Variant::XoChip => Quirks::XOCHIP
+    });
+    let mut processor = Processor::new(variant, quirks);
+    processor.set_unknown_opcode_policy(unknown_opcode_policy);
+
+    // Printing the seed lets a run be reproduced with --seed
+    if let Some(seed) = seed {
+        processor.set_seed(seed);
+    }
+    println!("{} {}", "Seed".green(), processor.seed());
+
+    // Tracing starts with the game when asked for on the command line, and
+    // is toggled with a hotkey
+    let _ = tracer.set_enabled(trace);
+
+    println!("{} drivers", "Initializing".green());
+    // Initialize graphics drivers
+    let mut graphics_drivers = match GraphicsDriver::new(&sdl_context, display_settings) {
+        Ok(driver) => driver,
+        Err(e) => {
+            println!("{}: can't open the window: {}", "Error".red(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Initialize the input drivers
+    let mut input_drivers = match InputDriver::new(&sdl_context, &keymap) {
+        Ok(driver) => driver,
+        Err(e) => {
+            println!("{}: can't read the keyboard: {}", "Error".red(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Initialize the audio drivers, the game runs silently without a device
+    let mut audio_drivers: Box<dyn AudioSink> = if audio {
+        match AudioDriver::new(&sdl_context, audio_settings) {
+            Ok(driver) => Box::new(driver),
+            Err(e) => {
+                println!("{}: no audio, {}", "Warning".yellow(), e);
+                Box::new(NullAudio::new())
+            }
+        }
+    } else {
+        Box::new(NullAudio::new())
+    };
+
+    // Create the cartridge driver
+    println!("{} cartridge", "Reading".green());
+    let cartridge_driver = match CartridgeDriver::new(game) {
+        Ok(driver) => driver,
+        Err(e) => {
+            println!("{}: can't read {}: {}", "Error".red(), game, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("{} {}", "Loading".green(), &game);
+    let rom = cartridge_driver.get();
+    if let Err(e) = processor.load(&rom) {
+        println!("{}: can't load {}: {}", "Error".red(), game, e);
+        return ExitCode::FAILURE;
+    }
+
+    // A recording only reproduces the game it was made with
+    if let Some(header) = replay.as_ref().map(InputReplay::header) {
+        if header.rom_hash != rom_hash(&rom) {
+            println!("{}: the recording was made with another game", "Error".red());
+            return ExitCode::FAILURE;
+        }
+        println!("{} the recording", "Replaying".green());
+    }
+    let mut recorder = record.map(|path| {
+        let recorder = InputRecorder::new(ReplayHeader {
+            rom_hash: rom_hash(&rom),
+            seed: processor.seed(),
+            speed,
+            variant,
+            quirks
+        });
+        (recorder, path)
+    });
+
+    // SUPER-CHIP games keep their RPL user flags between runs
+    if let Ok(flags) = fs::read(rpl_path(game)) {
+        processor.set_rpl_flags(&flags);
+    }
+
+    // Rewinding would desynchronize the recorded input, like loading a
+    // state
+    let mut rewind = if rewind_seconds > 0 && recorder.is_none() && replay.is_none() {
+        Some(Rewind::new(rewind_seconds))
+    } else {
+        None
+    };
+
+    // The debugger reads its commands from the console
+    let mut debugger = if debug {
+        println!("{} debugger, type `help` for the list of commands", "Starting".green());
+        Some((Debugger::new(), spawn_console()))
+    } else {
+        None
+    };
+
+    // Every frame shown is captured, from the start when asked for on the
+    // command line, then while toggled with the hotkey in the same format
+    let capture_format = capture_format.or(capture_path.map(|(_, format)| format)).unwrap_or_default();
+    let mut capture = capture_path.and_then(|(path, format)| start_capture(path, format, capture_scale));
+
+    // VM loop, the timers and the display run at 60 Hz and the processor
+    // executes a fixed number of instructions per frame
+    let mut scheduler = Scheduler::new(speed);
+    let mut last_frame = Instant::now();
+    let mut paused = false;
+    let mut faulted = false;
+    // Keys gone down or up not given to the processor yet, and the keypad
+    // of the last frame played
+    let mut key_events = Vec::new();
+    let mut previous_keypad = [false; 16];
+    'running: while let Ok(keypad) = input_drivers.poll() {
+        key_events.extend_from_slice(input_drivers.key_events());
+
+        for event in input_drivers.controller_events() {
+            match event {
+                ControllerEvent::Connected(name) => println!("{} {}", "Connected".green(), name),
+                ControllerEvent::Disconnected(name) => println!("{} {}", "Disconnected".green(), name)
+            }
+        }
+
+        for hotkey in input_drivers.hotkeys() {
+            match hotkey {
+                Hotkey::SaveState(slot) => save_state(&processor, game, slot),
+                // Loading a state or resetting would desynchronize the
+                // recorded input
+                Hotkey::LoadState(_) | Hotkey::Reset if recorder.is_some() || replay.is_some() => {
+                    println!("{}: can't load a state or reset while recording or replaying", "Error".red());
+                },
+                Hotkey::LoadState(slot) => {
+                    if load_state(&mut processor, game, slot) {
+                        filter.reset();
+                        draw(&mut graphics_drivers, &mut filter, &processor, palettes.current());
+                    }
+                },
+                Hotkey::Trace => {
+                    let enabled = !tracer.is_enabled();
+                    if let Err(e) = tracer.set_enabled(enabled) {
+                        println!("{}: can't write the trace: {}", "Error".red(), e);
+                    }
+                    println!("{} tracing", if enabled { "Started".green() } else { "Stopped".green() });
+                },
+                Hotkey::Mute => {
+                    let muted = !audio_drivers.is_muted();
+                    audio_drivers.set_muted(muted);
+                    println!("{} sound", if muted { "Muted".green() } else { "Unmuted".green() });
+                },
+                Hotkey::Pause => {
+                    paused = !paused;
+                    println!("{} the game", if paused { "Paused".green() } else { "Resumed".green() });
+                },
+                Hotkey::Reset => {
+                    // Start over with the same settings, keeping the RPL flags
+                    let mut reset = Processor::new(variant, quirks);
+                    reset.set_unknown_opcode_policy(unknown_opcode_policy);
+                    reset.set_seed(processor.seed());
+                    reset.set_rpl_flags(processor.rpl_flags());
+                    match reset.load(&rom) {
+                        Ok(()) => {
+                            processor = reset;
+                            scheduler.reset();
+                            if let Some(rewind) = rewind.as_mut() {
+                                rewind.clear();
+                            }
+                            filter.reset();
+                            draw(&mut graphics_drivers, &mut filter, &processor, palettes.current());
+                            println!("{} the game", "Reset".green());
+                        },
+                        Err(e) => println!("{}: can't reset the game: {}", "Error".red(), e)
+                    }
+                },
+                Hotkey::Fullscreen => {
+                    if let Err(e) = graphics_drivers.toggle_fullscreen() {
+                        println!("{}: can't switch to fullscreen: {}", "Error".red(), e);
+                    }
+                },
+                Hotkey::Palette => {
+                    let palette = palettes.cycle();
+                    println!("{} palette {}", "Using".green(), palette.name);
+                    draw(&mut graphics_drivers, &mut filter, &processor, palette);
+                },
+                Hotkey::Screenshot => screenshot(&graphics_drivers, game, screenshot_scale),
+                Hotkey::Capture => {
+                    capture = match capture.take() {
+                        Some(capture) => {
+                            stop_capture(capture);
+                            None
+                        },
+                        None => start_capture(&free_path(game, capture_format.extension()), capture_format, capture_scale)
+                    };
+                },
+                Hotkey::Quit => break 'running,
+                // Held rather than pressed, see is_rewinding
+                Hotkey::Rewind => ()
+            }
+        }
+
+        if let Some((debugger, console)) = debugger.as_mut() {
+            for line in console.try_iter() {
+                println!("{}", debugger.execute(&mut processor, &line));
+                prompt();
+            }
+        }
+
+        let now = Instant::now();
+        let frames = scheduler.advance(now - last_frame);
+        last_frame = now;
+
+        for _ in 0 .. frames {
+            let mut vram_changed = false;
+
+            // Holding the rewind key plays the last seconds backwards
+            if let Some(rewind) = rewind.as_mut().filter(|_| input_drivers.is_rewinding() && !is_paused(paused, &debugger)) {
+                if let Err(e) = rewind.rewind_frame(&mut processor) {
+                    println!("{}: can't rewind: {}", "Error".red(), e);
+                    rewind.clear();
+                }
+                audio_drivers.set_playing(false);
+                draw(&mut graphics_drivers, &mut filter, &processor, palettes.current());
+                capture_frame(&mut capture, graphics_drivers.frame());
+                continue;
+            }
+
+            // The keypad of the frame comes from the recording while it
+            // lasts, frames paused by the debugger are neither recorded
+            // nor replayed
+            let mut keypad = keypad;
+            if !is_paused(paused, &debugger) {
+                match replay.as_mut().map(InputReplay::next_frame) {
+                    Some(Some(recorded)) => keypad = recorded,
+                    Some(None) => {
+                        println!("{} the replay, the keyboard takes over", "Finished".green());
+                        replay = None;
+                    },
+                    None => ()
+                }
+                if let Some((recorder, _)) = recorder.as_mut() {
+                    recorder.record(keypad);
+                }
+
+                // FX0A gets the keys going down or up. A recording only
+                // keeps the keypad of every frame, the events are then found
+                // in it the way the replay finds them
+                let mut events = std::mem::take(&mut key_events);
+                if replay.is_some() || recorder.is_some() {
+                    events = KeyEvent::between(&previous_keypad, &keypad);
+                }
+                for event in events {
+                    processor.key_event(event);
+                }
+                previous_keypad = keypad;
+            }
+
+            for _ in 0 .. scheduler.instructions_per_frame() {
+                if is_paused(paused, &debugger) {
+                    break;
+                }
+
+                // A fault stops the game, or hands over to the debugger
+                let output = match tracer.tick(&mut processor, keypad) {
+                    Ok(output) => output,
+                    Err(e) => {
+                        if let Some(ring) = tracer.ring() {
+                            println!("{}", ring.dump());
+                        }
+                        println!("{}", e.report(&processor).red());
+                        match debugger.as_mut() {
+                            Some((debugger, _)) => {
+                                debugger.pause();
+                                prompt();
+                                break;
+                            },
+                            None => {
+                                faulted = true;
+                                break 'running;
+                            }
+                        }
+                    }
+                };
+                vram_changed |= output.vram_changed;
+
+                if let Some((debugger, _)) = debugger.as_mut() {
+                    if let Some(event) = debugger.after_tick(&processor, output.executed) {
+                        println!("{}", event);
+                        prompt();
+                    }
+                }
+
+                // A DXYN waiting for the vertical blank ends the frame, it
+                // is executed once the timers have ticked
+                if processor.is_waiting_for_vblank() {
+                    break;
+                }
+
+                if output.exited {
+                    println!("{} the game", "Exiting".green());
+                    break 'running;
+                }
+            }
+
+            // Time stands still while the debugger has the game paused
+            if !is_paused(paused, &debugger) {
+                processor.tick_timers();
+                if let Some(rewind) = rewind.as_mut() {
+                    rewind.frame(&processor);
+                }
+            }
+
+            if let Some(e) = tracer.take_error() {
+                println!("{}: can't write the trace, tracing stopped: {}", "Error".red(), e);
+            }
+
+            // The tone plays for as long as the sound timer runs
+            audio_drivers.set_pattern(processor.sound_pattern());
+            audio_drivers.set_playing(processor.sound_timer() > 0 && !is_paused(paused, &debugger));
+
+            // Refresh the screen once per frame if needed, the filters
+            // change the frames even when the display does not
+            if vram_changed || filter.is_animated() {
+                draw(&mut graphics_drivers, &mut filter, &processor, palettes.current());
+            }
+            capture_frame(&mut capture, graphics_drivers.frame());
+        }
+
+        // The window was resized or uncovered
+        if input_drivers.take_redraw() {
+            graphics_drivers.refresh();
+        }
+
+        // Wait for the next frame, the lag accumulated by the scheduler
+        // keeps the pace right whatever the sleep actually lasts
+        thread::sleep(scheduler.time_to_next_frame());
+    }
+
+    if let Some(capture) = capture {
+        stop_capture(capture);
+    }
+
+    if let Err(e) = tracer.flush() {
+        println!("{}: can't write the trace: {}", "Error".red(), e);
+    }
+
+    if let Some((recorder, path)) = recorder {
+        match fs::write(path, recorder.to_bytes()) {
+            Ok(()) => println!("{} {} frames to {}", "Recorded".green(), recorder.frames(), path),
+            Err(e) => println!("{}: can't save the recording {}: {}", "Error".red(), path, e)
+        }
+    }
+
+    // Unknown opcodes usually mean that the game was written for another
+    // variant
+    if !processor.unknown_opcodes().is_empty() {
+        print!("{} opcodes:\n{}", "Unknown".yellow(), processor.unknown_opcodes());
+    }
+
+    if processor.rpl_flags().iter().any(|&flag| flag != 0) {
+        if let Err(e) = fs::write(rpl_path(game), processor.rpl_flags()) {
+            println!("{}: can't save the RPL flags: {}", "Error".red(), e);
+        }
+    }
+
+    if faulted { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
+
+/// Make the next frame of the display and show it.
+fn draw(graphics_drivers: &mut GraphicsDriver, filter: &mut FrameFilter, processor: &Processor, palette: &Palette) {
+    graphics_drivers.draw(&filter.apply(processor.vram(), processor.hires(), palette));
+}
+
+/// Save the frame shown in the window as a PNG next to the ROM.
+fn screenshot(graphics_drivers: &GraphicsDriver, game: &str, scale: ScreenshotScale) {
+    let scale = match scale {
+        ScreenshotScale::Native => 1,
+        ScreenshotScale::Window => graphics_drivers.scale(),
+        ScreenshotScale::Times(scale) => scale
+    };
+    let png = encode_png(&graphics_drivers.frame().scaled(scale as usize));
+
+    let path = free_path(game, "png");
+    match fs::write(&path, png) {
+        Ok(()) => println!("{} screenshot to {}", "Saved".green(), path),
+        Err(e) => println!("{}: can't save {}: {}", "Error".red(), path, e)
+    }
+}
+
+/// Start writing the frames shown to a file.
+fn start_capture(path: &str, format: CaptureFormat, scale: usize) -> Option<(Box<dyn FrameSink>, String)> {
+    match fs::File::create(path).and_then(|file| format.sink(io::BufWriter::new(file), scale)) {
+        Ok(sink) => {
+            println!("{} to {}", "Capturing".green(), path);
+            Some((sink, path.to_string()))
+        },
+        Err(e) => {
+            println!("{}: can't create the capture {}: {}", "Error".red(), path, e);
+            None
+        }
+    }
+}
+
+fn stop_capture((mut sink, path): (Box<dyn FrameSink>, String)) {
+    match sink.finish() {
+        Ok(()) => println!("{} capture to {}", "Saved".green(), path),
+        Err(e) => println!("{}: can't write the capture {}: {}", "Error".red(), path, e)
+    }
+}
+
+/// Add a frame to the capture, which stops if it can't be written.
+fn capture_frame(capture: &mut Option<(Box<dyn FrameSink>, String)>, frame: &Frame) {
+    if let Some((sink, path)) = capture.as_mut() {
+        if let Err(e) = sink.frame(frame) {
+            println!("{}: can't write the capture {}, capture stopped: {}", "Error".red(), path, e);
+            *capture = None;
+        }
+    }
+}
+
+/// A file next to the ROM with the first free number, so that earlier
+/// screenshots and captures are kept, e.g. `resources/games/TETRIS-1.png`.
+fn free_path(game: &str, extension: &str) -> String {
+    (1 ..).map(|n| format!("{}-{}.{}", game, n, extension)).find(|path| !Path::new(path).exists()).unwrap()
+}
+
+/// Whether the game was paused with the hotkey or stopped by the debugger.
+fn is_paused(paused: bool, debugger: &Option<(Debugger, mpsc::Receiver<String>)>) -> bool {
+    paused || matches!(debugger, Some((debugger, _)) if !debugger.is_running())
+}
+
+/// Path of the SUPER-CHIP RPL user flags, stored next to the ROM.
+fn rpl_path(game: &str) -> String {
+    format!("{}.rpl", game)
+}
+
+/// The config given with `--config`, or `chip8.toml` in the working
+/// directory, empty if there is none. Parsed once for the key bindings and
+/// the palettes.
+fn read_config(path: Option<&str>) -> Option<toml::Value> {
+    let text = match (path, fs::read_to_string(path.unwrap_or(CONFIG_PATH))) {
+        (_, Ok(text)) => text,
+        (None, Err(_)) => String::new(),
+        (Some(path), Err(e)) => {
+            println!("{}: can't read {}: {}", "Error".red(), path, e);
+            return None;
+        }
+    };
+
+    match text.parse() {
+        Ok(config) => Some(config),
+        Err(e) => {
+            println!("{}: can't read {}: {}", "Error".red(), path.unwrap_or(CONFIG_PATH), e);
+            None
+        }
+    }
+}
+
+/// Path of a save slot, stored next to the ROM.
+fn state_path(game: &str, slot: u8) -> String {
+    format!("{}.state{}", game, slot)
+}
+
+fn save_state(processor: &Processor, game: &str, slot: u8) {
+    let path = state_path(game, slot);
+    match fs::write(&path, processor.save_state()) {
+        Ok(()) => println!("{} state to {}", "Saved".green(), path),
+        Err(e) => println!("{}: can't save {}: {}", "Error".red(), path, e)
+    }
+}
+
+fn load_state(processor: &mut Processor, game: &str, slot: u8) -> bool {
+    let path = state_path(game, slot);
+    match fs::read(&path).and_then(|state| processor.load_state(&state)) {
+        Ok(()) => {
+            println!("{} state from {}", "Loaded".green(), path);
+            true
+        },
+        Err(e) => {
+            println!("{}: can't load {}: {}", "Error".red(), path, e);
+            false
+        }
+    }
+}
+
+/// Read the console on a separate thread so the window stays responsive.
+fn spawn_console() -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        prompt();
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break
+            };
+
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
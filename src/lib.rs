@@ -0,0 +1,9 @@
+//! Chip-8 virtual machine.
+//!
+//! The `Processor` and everything it needs to run a game have no dependency
+//! on SDL2, so the VM can be embedded in tools, servers and test harnesses.
+//! The SDL2 front-end drivers are only available with the `sdl` feature.
+
+pub mod core;
+
+pub use crate::core::*;
@@ -1,55 +1,395 @@
-use std::{env, thread, time};
+use std::{env, fs, io};
+use std::process::ExitCode;
 use colored::*;
 
-mod core;
+use chip8_emulator::*;
 
-use crate::core::*;
+#[cfg(feature = "sdl")]
+mod frontend;
 
-fn main() {
-    // Read the game name
-    let args: Vec<String> = env::args().collect();
-    if args.len() <= 1 {
-        println!("{}: no game specified", "Error".red());
-        return;
+const USAGE: &str = "\
+Usage:
+  chip8 [run] <rom> [--debug] [--xochip] [--quirks <preset>] [--speed <hz>]
+                                 Play a game, XO-CHIP games are detected
+                                 from the .xo8 extension. The quirks are
+                                 default, vip, chip48, schip or xochip,
+                                 optionally followed by ,display-wait or
+                                 ,add-i-overflow, the speed is in
+                                 instructions per second (600)
+      [--unknown <policy>]       On unknown opcodes halt (the default), skip
+                                 them as earlier versions did, or treat 0nnn
+                                 (sys) as a no-op. They are listed when the
+                                 game ends
+      [--seed <n>]               Seed of the random numbers, to reproduce a run
+      [--rewind <seconds>]       Play kept for rewinding with Backspace (10),
+                                 0 disables it
+      [--config <file>]          Key bindings and palettes, chip8.toml by
+                                 default
+      [--palette <name>]         classic, green, amber, lcd, contrast or one
+                                 of the config, Tab cycles through them
+      [--record <file>]          Save the keypad of every frame
+      [--replay <file>]          Play a recorded session again
+      [--trace <file>] [--trace-bin <file>] [--trace-ring <n>]
+                                 Record every instruction as text, binary, or
+                                 keep the last n to print on a fault. F10
+                                 starts and stops tracing
+      [--scale <n>] [--scaling <integer|fit>] [--fullscreen]
+                                 Size of a pixel at start (16), how the screen
+                                 fills the window, F11 toggles fullscreen
+      [--filter <mode>]          Anti-flicker: vblank, blend[:frames] or
+                                 phosphor[:decay]
+      [--screenshot-scale <size>]
+                                 Screenshots taken with PrintScreen: native,
+                                 window (pixels as in the window) or a scale
+      [--capture <file>] [--capture-format <gif|y4m>] [--capture-scale <n>]
+                                 Save every frame as a GIF or Y4M video from
+                                 the start, Insert starts and stops captures
+                                 (gif), a hi-res pixel being n pixels (4)
+      [--no-audio] [--frequency <hz>] [--volume <0-100>] [--waveform <shape>]
+                                 Tone played by the sound timer, a square,
+                                 sine or triangle wave (440 Hz, 25%)
+  chip8 capture <rom> -o <file> [--frames <n>] [--replay <file>]
+      [--xochip] [--quirks <preset>] [--speed <hz>] [--seed <n>]
+      [--palette <theme>] [--filter <mode>] [--capture-scale <n>]
+                                 Run a game without a window and save its
+                                 frames as a GIF or Y4M video (.gif, .y4m),
+                                 600 frames (10 seconds) by default
+  chip8 disasm <rom> [--source]  Print the disassembly of a ROM, or source
+                                 code for the assembler with --source
+  chip8 asm <file> [-o <rom>]    Assemble a source file into a ROM";
+
+/// Exits with 1 when the command fails, so that scripts can tell.
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        None | Some("help") | Some("--help") => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        },
+        Some("disasm") => disasm(&args[1..]),
+        Some("asm") => asm(&args[1..]),
+        Some("capture") => capture(&args[1..]),
+        Some("run") => run(&args[1..]),
+        Some(_) => run(&args)
     }
+}
 
-    let game = &args[1];
+#[cfg(feature = "sdl")]
+fn run(args: &[String]) -> ExitCode {
+    frontend::run(args)
+}
 
-    println!("{} Chip-8 emulator", "Initializing".green());
+#[cfg(not(feature = "sdl"))]
+fn run(_args: &[String]) -> ExitCode {
+    println!("{}: this build has no SDL2 front-end, enable the `sdl` feature", "Error".red());
+    ExitCode::FAILURE
+}
 
-    println!("{} SDL2", "Initializing".green());
-    let sdl_context = sdl2::init().unwrap();
+/// Run a game without a window nor sound, writing every frame to a
+/// capture. The keypad comes from a recording, or stays up.
+fn capture(args: &[String]) -> ExitCode {
+    let mut rom = None;
+    let mut output = None;
+    let mut frames = 10 * TIMER_FREQUENCY as u64;
+    let mut replay = None;
+    let mut xochip = false;
+    let mut quirks = None;
+    let mut speed = DEFAULT_SPEED;
+    let mut seed = None;
+    let mut palette = Palette::default();
+    let mut filter = FrameFilter::new(Filter::default());
+    let mut scale = DEFAULT_CAPTURE_SCALE;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => {
+                match args.next().filter(|path| CaptureFormat::from_path(path).is_some()) {
+                    Some(path) => output = Some(path),
+                    None => {
+                        println!("{}: -o expects a file ending in .{}", "Error".red(), CaptureFormat::NAMES.join(" or ."));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--frames" => {
+                match args.next().and_then(|value| value.parse().ok()) {
+                    Some(value) => frames = value,
+                    None => {
+                        println!("{}: --frames expects a number of frames", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--replay" => {
+                let path = args.next().map(String::as_str).unwrap_or("");
+                match fs::read(path).and_then(|data| InputReplay::from_bytes(&data)) {
+                    Ok(recording) => replay = Some(recording),
+                    Err(e) => {
+                        println!("{}: can't read the recording `{}`: {}", "Error".red(), path, e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--xochip" => xochip = true,
+            "--quirks" => {
+                let name = args.next().map(String::as_str).unwrap_or("");
+                match Quirks::from_name(name) {
+                    Some(value) => quirks = Some(value),
+                    None => {
+                        println!("{}: unknown quirks `{}`, expected one of {} then any of {}, separated by commas",
+                                 "Error".red(), name, Quirks::PRESETS.join(", "), Quirks::OPTIONS.join(", "));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--speed" => {
+                match args.next().and_then(|value| value.parse().ok()).filter(|&speed| speed > 0) {
+                    Some(value) => speed = value,
+                    None => {
+                        println!("{}: --speed expects a number of instructions per second", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--seed" => {
+                match args.next().and_then(|value| value.parse().ok()) {
+                    Some(value) => seed = Some(value),
+                    None => {
+                        println!("{}: --seed expects a number", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--palette" => {
+                let name = args.next().map(String::as_str).unwrap_or("");
+                match Palette::theme(name) {
+                    Some(theme) => palette = theme,
+                    None => {
+                        println!("{}: unknown palette `{}`, expected one of {}",
+                                 "Error".red(), name, Palette::THEMES.join(", "));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--filter" => {
+                let name = args.next().map(String::as_str).unwrap_or("");
+                match Filter::from_name(name) {
+                    Some(mode) => filter = FrameFilter::new(mode),
+                    None => {
+                        println!("{}: unknown filter `{}`, expected one of {}, blend:<frames> or phosphor:<decay>",
+                                 "Error".red(), name, Filter::NAMES.join(", "));
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            "--capture-scale" => {
+                match args.next().and_then(|value| value.parse().ok()).filter(|&scale| scale > 0) {
+                    Some(value) => scale = value,
+                    None => {
+                        println!("{}: --capture-scale expects the size of a pixel", "Error".red());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            },
+            _ => rom = Some(arg)
+        }
+    }
 
-    // Create the VM
-    let mut processor = Processor::new();
+    let (rom, output) = match (rom, output) {
+        (Some(rom), Some(output)) => (rom, output),
+        _ => {
+            println!("{}: specify the ROM and the capture with -o", "Error".red());
+            return ExitCode::FAILURE;
+        }
+    };
 
-    println!("{} drivers", "Initializing".green());
-    // Initialize graphics drivers
-    let mut graphics_drivers = GraphicsDriver::new(&sdl_context);
-    // Initialize the input drivers
-    let mut input_drivers = InputDriver::new(&sdl_context);
+    // A replay runs with the settings it was recorded with
+    if let Some(header) = replay.as_ref().map(InputReplay::header) {
+        xochip = header.variant == Variant::XoChip;
+        quirks = Some(header.quirks);
+        speed = header.speed;
+        seed = Some(header.seed);
+    }
 
-    // Create the cartridge driver
-    println!("{} cartridge", "Reading".green());
-    let cartridge_driver = CartridgeDriver::new(&game).unwrap();
+    let game = match CartridgeDriver::new(rom) {
+        Ok(cartridge) => cartridge.get(),
+        Err(e) => {
+            println!("{}: can't read {}: {}", "Error".red(), rom, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if replay.as_ref().is_some_and(|replay| replay.header().rom_hash != rom_hash(&game)) {
+        println!("{}: the recording was made with another game", "Error".red());
+        return ExitCode::FAILURE;
+    }
 
-    println!("{} {}", "Loading".green(), &game);
-    processor.load(&cartridge_driver.get());
+    // As in the window, the quirks may come from a file next to the ROM
+    let variant = if xochip || rom.ends_with(".xo8") { Variant::XoChip } else { Variant::Chip8 };
+    let mut processor = Processor::new(variant, quirks.or_else(|| read_quirks(rom)).unwrap_or(match variant {
+        Variant::Chip8  => Quirks::DEFAULT,
+        Variant::XoChip => Quirks::XOCHIP
+    }));
+    if let Some(seed) = seed {
+        processor.set_seed(seed);
+    }
+    if let Err(e) = processor.load(&game) {
+        println!("{}: can't load {}: {}", "Error".red(), rom, e);
+        return ExitCode::FAILURE;
+    }
 
-    // VM loop
-    while let Ok(keypad) = input_drivers.poll() {
-        let output = processor.tick(keypad).unwrap();
+    let format = CaptureFormat::from_path(output).unwrap_or_default();
+    let mut sink = match fs::File::create(output).and_then(|file| format.sink(io::BufWriter::new(file), scale)) {
+        Ok(sink) => sink,
+        Err(e) => {
+            println!("{}: can't create the capture {}: {}", "Error".red(), output, e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-        // Refresh the screen if needed
-        if output.vram_changed {
-            graphics_drivers.draw(&output.vram);
+    // The same frames as in the window: the instructions of the frame, the
+    // timers, then the display
+    let mut scheduler = Scheduler::new(speed);
+    let mut captured = 0;
+    let mut faulted = false;
+    let mut previous_keypad = [false; 16];
+    while captured < frames {
+        let keypad = replay.as_mut().and_then(InputReplay::next_frame).unwrap_or([false; 16]);
+        for event in KeyEvent::between(&previous_keypad, &keypad) {
+            processor.key_event(event);
+        }
+        previous_keypad = keypad;
+        let mut running = true;
+        for _ in 0 .. scheduler.instructions_per_frame() {
+            match processor.tick(keypad) {
+                Ok(output) if output.exited => running = false,
+                Ok(_) => (),
+                Err(e) => {
+                    println!("{}", e.report(&processor).red());
+                    faulted = true;
+                    running = false;
+                }
+            }
+            if !running || processor.is_waiting_for_vblank() {
+                break;
+            }
         }
+        processor.tick_timers();
 
-        // Beep if needed
-        if output.beep_request {
+        let frame = filter.apply(processor.vram(), processor.hires(), &palette);
+        if let Err(e) = sink.frame(&frame) {
+            println!("{}: can't write the capture {}: {}", "Error".red(), output, e);
+            return ExitCode::FAILURE;
+        }
+        captured += 1;
 
+        // The capture ends with the game
+        if !running {
+            break;
         }
+    }
 
-        thread::sleep(time::Duration::from_millis(4));
+    // The frames up to a fault are kept, the fault still fails the command
+    match sink.finish() {
+        Ok(()) => println!("{} {} frames to {}", "Captured".green(), captured, output),
+        Err(e) => {
+            println!("{}: can't write the capture {}: {}", "Error".red(), output, e);
+            return ExitCode::FAILURE;
+        }
     }
+
+    if faulted { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
+
+fn disasm(args: &[String]) -> ExitCode {
+    let source = args.iter().any(|arg| arg == "--source");
+    let rom = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(rom) => rom,
+        None => {
+            println!("{}: no ROM specified", "Error".red());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match CartridgeDriver::new(rom) {
+        Ok(cartridge) => {
+            let disassembly = Disassembly::new(&cartridge.get());
+            if source {
+                print!("{}", disassembly.source());
+            } else {
+                print!("{}", disassembly.listing());
+            }
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            println!("{}: can't read {}: {}", "Error".red(), rom, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn asm(args: &[String]) -> ExitCode {
+    let mut input = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            _ => input = Some(arg)
+        }
+    }
+
+    let input = match input {
+        Some(input) => input,
+        None => {
+            println!("{}: no source file specified", "Error".red());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Without -o the ROM is written next to the source, without the extension
+    let output = match output {
+        Some(output) => output.clone(),
+        None => input.trim_end_matches(".c8s").to_string()
+    };
+
+    if output == *input {
+        println!("{}: specify the ROM with -o", "Error".red());
+        return ExitCode::FAILURE;
+    }
+
+    let rom = match assemble_file(input) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("{}: {}", "Error".red(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Bigger programs only fit in the 64K memory of XO-CHIP
+    if rom.len() > 0xfff - 0x200 {
+        println!("{}: {} bytes is too big for CHIP-8, run it with --xochip", "Warning".yellow(), rom.len());
+    }
+
+    match fs::write(&output, &rom) {
+        Ok(()) => {
+            println!("{} {} ({} bytes)", "Assembled".green(), output, rom.len());
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            println!("{}: can't write {}: {}", "Error".red(), output, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Quirks named in the `.quirks` file next to the ROM, if any.
+pub(crate) fn read_quirks(game: &str) -> Option<Quirks> {
+    let name = fs::read_to_string(format!("{}.quirks", game)).ok()?;
+    let quirks = Quirks::from_name(name.trim());
+    if quirks.is_none() {
+        println!("{}: unknown quirks `{}` in {}.quirks", "Error".red(), name.trim(), game);
+    }
+
+    quirks
 }